@@ -0,0 +1,170 @@
+// Copyright 2013 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+/*!
+
+Allocators for the memory libcss takes for sheets, select contexts and
+their results.
+
+An allocator is given to libcss as the `pw` of its allocation callback, so
+every sheet or context created with it allocates through it until destroyed.
+
+*/
+
+use std::libc;
+use std::libc::{c_void, size_t};
+use std::ptr;
+use std::cast::{transmute, transmute_mut};
+use std::unstable::atomics::{AtomicUint, SeqCst};
+use extra::arc::Arc;
+use realloc;
+
+pub trait CssAllocator {
+    /// Like realloc(3): a null `ptr` allocates, a zero `len` frees and null
+    /// is returned when out of memory
+    fn realloc(&self, ptr: *mut c_void, len: size_t) -> *mut c_void;
+
+    /// The bytes handed out and not yet freed, for allocators that keep count
+    fn bytes_allocated(&self) -> Option<uint> { None }
+}
+
+/// A shared reference to an allocator, kept by everything allocated with it
+/// so that it lives as long as they do
+#[deriving(Clone)]
+pub struct CssAllocatorHandle {
+    priv allocator: Arc<~CssAllocator:Send+Freeze>
+}
+
+impl CssAllocatorHandle {
+    pub fn new(allocator: ~CssAllocator:Send+Freeze) -> CssAllocatorHandle {
+        CssAllocatorHandle { allocator: Arc::new(allocator) }
+    }
+
+    pub fn bytes_allocated(&self) -> Option<uint> {
+        self.allocator.get().bytes_allocated()
+    }
+
+    // What libcss is given as pw, read back by realloc_with
+    pub fn as_pw(&self) -> *c_void {
+        let allocator: &~CssAllocator:Send+Freeze = self.allocator.get();
+        unsafe { transmute(allocator) }
+    }
+}
+
+/// A counting allocator over `allocator`, for one sheet, context or style to
+/// allocate through so that its memory use is known exactly
+pub fn meter_for(allocator: &Option<CssAllocatorHandle>) -> CssAllocatorHandle {
+    CssAllocatorHandle::new(~CssCountingAllocator::over(allocator.clone()) as ~CssAllocator:Send+Freeze)
+}
+
+// Allocates with the allocator behind a pw from CssAllocatorHandle::as_pw
+pub fn realloc_with(pw: *c_void, ptr: *mut c_void, len: size_t) -> *mut c_void {
+    let allocator: &~CssAllocator:Send+Freeze = unsafe { transmute(pw) };
+    allocator.realloc(ptr, len)
+}
+
+/// The C library's allocator, which is what libcss gets by default
+pub struct CssLibcAllocator;
+
+impl CssAllocator for CssLibcAllocator {
+    fn realloc(&self, ptr: *mut c_void, len: size_t) -> *mut c_void {
+        realloc(ptr, len, ptr::null())
+    }
+}
+
+/// Allocates with the C library, or another allocator, and counts the bytes
+/// in use. With a limit, allocations that would go over it fail, which libcss
+/// reports as `CssNoMem`. Allocations racing from several tasks may overshoot
+/// it a little
+pub struct CssCountingAllocator {
+    priv allocated: AtomicUint,
+    priv limit: Option<uint>,
+    // None for the C library's allocator
+    priv inner: Option<CssAllocatorHandle>
+}
+
+impl CssCountingAllocator {
+    pub fn new() -> CssCountingAllocator {
+        CssCountingAllocator { allocated: AtomicUint::new(0), limit: None, inner: None }
+    }
+
+    pub fn with_limit(limit: uint) -> CssCountingAllocator {
+        CssCountingAllocator { allocated: AtomicUint::new(0), limit: Some(limit), inner: None }
+    }
+
+    /// Counts what is allocated through `inner`, or through the C library for
+    /// None. Sheets, contexts and styles each count their memory with one
+    pub fn over(inner: Option<CssAllocatorHandle>) -> CssCountingAllocator {
+        CssCountingAllocator { allocated: AtomicUint::new(0), limit: None, inner: inner }
+    }
+
+    // The count is only ever touched atomically, so sharing it is safe
+    fn count<'a>(&'a self) -> &'a mut AtomicUint {
+        unsafe { transmute_mut(&self.allocated) }
+    }
+
+    // Takes the block, header and all, from the allocator being counted
+    #[fixed_stack_segment]
+    fn realloc_block(&self, block: *mut c_void, len: size_t) -> *mut c_void {
+        match self.inner {
+            Some(ref inner) => inner.allocator.get().realloc(block, len),
+            None if len == 0 => {
+                unsafe { libc::free(block as *c_void); }
+                ptr::mut_null()
+            }
+            None => unsafe { libc::realloc(block, len) }
+        }
+    }
+}
+
+// Every block starts with its length, so that frees and reallocations know
+// how much to take off the count. Sixteen bytes keep the rest aligned
+static HEADER: uint = 16;
+
+impl CssAllocator for CssCountingAllocator {
+    #[fixed_stack_segment]
+    fn realloc(&self, ptr: *mut c_void, len: size_t) -> *mut c_void {
+        unsafe {
+            let (block, old_len) = if ptr.is_null() {
+                (ptr::mut_null(), 0)
+            } else {
+                let block = (ptr as uint - HEADER) as *mut c_void;
+                (block, *(block as *size_t) as uint)
+            };
+            let len = len as uint;
+            if len == 0 {
+                if block.is_not_null() {
+                    self.realloc_block(block, 0);
+                    self.count().fetch_sub(old_len, SeqCst);
+                }
+                return ptr::mut_null();
+            }
+            match self.limit {
+                Some(limit) if len > old_len && self.count().load(SeqCst) + len - old_len > limit => {
+                    return ptr::mut_null();
+                }
+                _ => ()
+            }
+            // On failure the old block is left as it was
+            let block = self.realloc_block(block, (len + HEADER) as size_t);
+            if block.is_null() {
+                return ptr::mut_null();
+            }
+            *(block as *mut size_t) = len as size_t;
+            self.count().fetch_add(len, SeqCst);
+            self.count().fetch_sub(old_len, SeqCst);
+            (block as uint + HEADER) as *mut c_void
+        }
+    }
+
+    fn bytes_allocated(&self) -> Option<uint> {
+        Some(self.count().load(SeqCst))
+    }
+}
+
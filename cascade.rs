@@ -0,0 +1,189 @@
+// Copyright 2013 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+/*!
+
+Styles a whole tree: selection for every node, then composition with the
+parent's style so that inherited properties get their values, in document
+order. The tree is walked with the `CssTreeHandler` methods, a node at a
+time rather than by recursion, so deep documents do not run out of stack.
+
+*/
+
+use select::{CssSelectCtx, CssSelectHandler, CssTreeHandler, CssSelectResults, CssPseudoElement};
+use select::{CssPseudoElementNone, CssPseudoElementFirstLine, CssPseudoElementFirstLetter};
+use select::{CssPseudoElementBefore, CssPseudoElementAfter};
+use computed::{CssOwnedComputedStyle, FontSizeComputer, compose_owned};
+use hint::{CssHint, CssHintLength};
+use types::*;
+use media::CssMedia;
+use length::LengthContext;
+use fixed::CssFixed;
+
+/// The final styles of a node and of the pseudo-elements it has rules for
+pub struct CssCascadedStyles {
+    style: CssOwnedComputedStyle,
+    first_line: Option<CssOwnedComputedStyle>,
+    first_letter: Option<CssOwnedComputedStyle>,
+    before: Option<CssOwnedComputedStyle>,
+    after: Option<CssOwnedComputedStyle>
+}
+
+/// Resolves font sizes relative to the parent's, assuming 96 pixels to the
+/// inch and an x-height of half an em. Keywords like `larger` are not passed
+/// to the computer as lengths, so they keep the parent's size
+pub struct CssDefaultFontSizeComputer {
+    // The size for the root, in pixels
+    medium: CssFixed
+}
+
+impl CssDefaultFontSizeComputer {
+    pub fn new() -> CssDefaultFontSizeComputer {
+        CssDefaultFontSizeComputer { medium: CssFixed::from_int(16) }
+    }
+}
+
+impl FontSizeComputer for CssDefaultFontSizeComputer {
+    fn compute_font_size(&self, parent: &Option<CssHint>, child: &CssHint) -> CssHint {
+        let parent = match *parent {
+            Some(CssHintLength(CssUnitPx(size))) => size,
+            _ => self.medium
+        };
+        // em and % are of the parent's size
+        let ctx = LengthContext::new(parent, parent);
+        let size = match *child {
+            CssHintLength(ref length) => match length.to_px(&ctx) {
+                Some(size) => size,
+                None => parent
+            },
+            _ => parent
+        };
+        CssHintLength(CssUnitPx(size))
+    }
+}
+
+/// Styles `root` and everything below it, calling `visitor` with each node's
+/// styles in document order. Font sizes are resolved by a
+/// `CssDefaultFontSizeComputer`. `root` must be the root of the document, as
+/// nothing is inherited into it; use `cascade_subtree` for part of a tree
+pub fn cascade_tree<N, H: CssSelectHandler<N> + CssTreeHandler<N>>(root: &N,
+                                                                   ctx: &CssSelectCtx,
+                                                                   media: CssMedia,
+                                                                   handler: &H,
+                                                                   visitor: &fn(&N, &CssCascadedStyles)) {
+    let font_size = @CssDefaultFontSizeComputer::new() as @FontSizeComputer;
+    cascade_tree_with(root, ctx, media, handler, font_size, visitor)
+}
+
+/// As `cascade_tree`, with font sizes resolved by `font_size`. Fails if
+/// `root` has a parent
+pub fn cascade_tree_with<N, H: CssSelectHandler<N> + CssTreeHandler<N>>(root: &N,
+                                                                        ctx: &CssSelectCtx,
+                                                                        media: CssMedia,
+                                                                        handler: &H,
+                                                                        font_size: @FontSizeComputer,
+                                                                        visitor: &fn(&N, &CssCascadedStyles)) {
+    if handler.parent_node(root).is_some() {
+        fail!(~"cascade_tree was given a node with a parent; use cascade_subtree");
+    }
+    cascade_from(root, None, ctx, media, handler, font_size, visitor)
+}
+
+/// Styles `root` and everything below it, like `cascade_tree`, for a node
+/// inside the document. `parent_style` is the final style of its parent,
+/// e.g. as an earlier cascade left it
+pub fn cascade_subtree<N, H: CssSelectHandler<N> + CssTreeHandler<N>>(root: &N,
+                                                                      parent_style: &CssOwnedComputedStyle,
+                                                                      ctx: &CssSelectCtx,
+                                                                      media: CssMedia,
+                                                                      handler: &H,
+                                                                      font_size: @FontSizeComputer,
+                                                                      visitor: &fn(&N, &CssCascadedStyles)) {
+    cascade_from(root, Some(parent_style), ctx, media, handler, font_size, visitor)
+}
+
+fn cascade_from<N, H: CssSelectHandler<N> + CssTreeHandler<N>>(root: &N,
+                                                               parent: Option<&CssOwnedComputedStyle>,
+                                                               ctx: &CssSelectCtx,
+                                                               media: CssMedia,
+                                                               handler: &H,
+                                                               font_size: @FontSizeComputer,
+                                                               visitor: &fn(&N, &CssCascadedStyles)) {
+    let style = cascade_node(root, parent, ctx, media, handler, font_size, visitor);
+    // The nodes still to style, each with its parent's style. The next in
+    // document order is last
+    let mut pending = ~[];
+    push_children(root, &style, handler, &mut pending);
+    while !pending.is_empty() {
+        let (node, parent_style) = pending.pop();
+        let style = cascade_node(&node, Some(&parent_style), ctx, media, handler, font_size, visitor);
+        push_children(&node, &style, handler, &mut pending);
+    }
+}
+
+fn push_children<N, H: CssTreeHandler<N>>(node: &N,
+                                          style: &CssOwnedComputedStyle,
+                                          handler: &H,
+                                          pending: &mut ~[(N, CssOwnedComputedStyle)]) {
+    let mut children = ~[];
+    let mut child = handler.first_child_node(node);
+    loop {
+        match child {
+            Some(node) => {
+                child = handler.next_sibling_node(&node);
+                children.push(node);
+            }
+            None => break
+        }
+    }
+    // Reversed, so that the first child is styled first
+    while !children.is_empty() {
+        pending.push((children.pop(), style.clone()));
+    }
+}
+
+// Styles one node and hands the styles to the visitor, returning the node's
+// own style for its children to inherit from
+fn cascade_node<N, H: CssSelectHandler<N> + CssTreeHandler<N>>(node: &N,
+                                                               parent: Option<&CssOwnedComputedStyle>,
+                                                               ctx: &CssSelectCtx,
+                                                               media: CssMedia,
+                                                               handler: &H,
+                                                               font_size: @FontSizeComputer,
+                                                               visitor: &fn(&N, &CssCascadedStyles)) -> CssOwnedComputedStyle {
+    let results = ctx.select_style_in_tree(node, media, None, handler);
+    let style = match parent {
+        Some(parent) => compose_owned(&parent.style(), &results.computed_style(CssPseudoElementNone), font_size),
+        // The document root inherits nothing, and libcss has already made
+        // its values absolute
+        None => results.owned_computed_style(CssPseudoElementNone)
+    };
+    // Pseudo-elements inherit from the element they belong to
+    let pseudo = |element: CssPseudoElement| pseudo_element_style(&results, element, &style, font_size);
+    let styles = CssCascadedStyles {
+        first_line: pseudo(CssPseudoElementFirstLine),
+        first_letter: pseudo(CssPseudoElementFirstLetter),
+        before: pseudo(CssPseudoElementBefore),
+        after: pseudo(CssPseudoElementAfter),
+        style: style.clone()
+    };
+    visitor(node, &styles);
+    styles.style
+}
+
+fn pseudo_element_style(results: &CssSelectResults,
+                        element: CssPseudoElement,
+                        parent: &CssOwnedComputedStyle,
+                        font_size: @FontSizeComputer) -> Option<CssOwnedComputedStyle> {
+    if results.has_style(element) {
+        Some(compose_owned(&parent.style(), &results.computed_style(element), font_size))
+    } else {
+        None
+    }
+}
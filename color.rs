@@ -0,0 +1,322 @@
+// Copyright 2013 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+/*!
+
+Making, reading, blending and writing `CssColor`s without a stylesheet.
+
+*/
+
+use types::CssColor;
+use fixed::CssFixed;
+use errors::CssInvalid;
+use CssResult;
+use std::ascii::StrAsciiExt;
+
+impl CssColor {
+    pub fn rgb(r: u8, g: u8, b: u8) -> CssColor {
+        CssColor { r: r, g: g, b: b, a: 255 }
+    }
+
+    pub fn rgba(r: u8, g: u8, b: u8, a: u8) -> CssColor {
+        CssColor { r: r, g: g, b: b, a: a }
+    }
+
+    /// Hue in degrees, saturation and lightness from 0 to 1
+    pub fn hsl(h: f64, s: f64, l: f64) -> CssColor {
+        CssColor::hsla(h, s, l, 255)
+    }
+
+    pub fn hsla(h: f64, s: f64, l: f64, a: u8) -> CssColor {
+        // The algorithm from CSS Color Module Level 3
+        let h = ((h % 360.0) + 360.0) % 360.0 / 360.0;
+        let s = clamp_unit(s);
+        let l = clamp_unit(l);
+        let m2 = if l <= 0.5 { l * (s + 1.0) } else { l + s - l * s };
+        let m1 = l * 2.0 - m2;
+        CssColor {
+            r: to_channel(hue_to_rgb(m1, m2, h + 1.0 / 3.0)),
+            g: to_channel(hue_to_rgb(m1, m2, h)),
+            b: to_channel(hue_to_rgb(m1, m2, h - 1.0 / 3.0)),
+            a: a
+        }
+    }
+
+    /// Reads `#rgb`, `#rgba`, `#rrggbb`, `#rrggbbaa`, `transparent` or one
+    /// of the CSS 3 colour keywords, ignoring case
+    pub fn parse(text: &str) -> CssResult<CssColor> {
+        if text.starts_with("#") {
+            return parse_hex(text.slice_from(1));
+        }
+        let name = text.to_ascii_lower();
+        if name.as_slice() == "transparent" {
+            return Ok(CssColor::rgba(0, 0, 0, 0));
+        }
+        for &(keyword, rgb) in NAMED_COLORS.iter() {
+            if keyword == name.as_slice() {
+                return Ok(CssColor::rgb((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8));
+            }
+        }
+        Err(CssInvalid)
+    }
+
+    /// This colour painted on top of `backdrop`. Channels are blended
+    /// premultiplied by their alpha
+    pub fn over(&self, backdrop: &CssColor) -> CssColor {
+        let source_alpha = self.a as f64 / 255.0;
+        // How much of the backdrop shows through
+        let backdrop_alpha = backdrop.a as f64 / 255.0 * (1.0 - source_alpha);
+        let alpha = source_alpha + backdrop_alpha;
+        if alpha == 0.0 {
+            return CssColor::rgba(0, 0, 0, 0);
+        }
+        let blend = |source: u8, backdrop: u8| {
+            let premultiplied = source as f64 * source_alpha + backdrop as f64 * backdrop_alpha;
+            to_channel(premultiplied / alpha / 255.0)
+        };
+        CssColor {
+            r: blend(self.r, backdrop.r),
+            g: blend(self.g, backdrop.g),
+            b: blend(self.b, backdrop.b),
+            a: to_channel(alpha)
+        }
+    }
+
+    /// The WCAG 2 relative luminance, from 0 for black to 1 for white.
+    /// Alpha is ignored, so composite translucent colours first
+    pub fn relative_luminance(&self) -> f64 {
+        0.2126 * linear_channel(self.r) + 0.7152 * linear_channel(self.g) + 0.0722 * linear_channel(self.b)
+    }
+
+    /// The WCAG 2 contrast ratio with `other`, from 1 to 21
+    pub fn contrast_ratio(&self, other: &CssColor) -> f64 {
+        let (a, b) = (self.relative_luminance(), other.relative_luminance());
+        let (lighter, darker) = if a > b { (a, b) } else { (b, a) };
+        (lighter + 0.05) / (darker + 0.05)
+    }
+
+    pub fn is_opaque(&self) -> bool {
+        self.a == 255
+    }
+
+    /// `#rrggbb`, leaving out the alpha
+    pub fn to_hex(&self) -> ~str {
+        fmt!("#%02x%02x%02x", self.r as uint, self.g as uint, self.b as uint)
+    }
+
+    /// `rgba(r, g, b, a)` with the alpha from 0 to 1
+    pub fn to_rgba(&self) -> ~str {
+        let alpha = CssFixed::from_f64(self.a as f64 / 255.0);
+        fmt!("rgba(%u, %u, %u, %s)", self.r as uint, self.g as uint, self.b as uint, alpha.to_str())
+    }
+
+    /// The hex form when opaque, as it is shorter, and `rgba()` otherwise
+    pub fn to_css(&self) -> ~str {
+        if self.is_opaque() { self.to_hex() } else { self.to_rgba() }
+    }
+}
+
+fn clamp_unit(value: f64) -> f64 {
+    if value < 0.0 { 0.0 } else if value > 1.0 { 1.0 } else { value }
+}
+
+// From 0-1 to 0-255, rounded
+fn to_channel(value: f64) -> u8 {
+    (clamp_unit(value) * 255.0 + 0.5) as u8
+}
+
+// An sRGB channel without its gamma, from 0 to 1
+fn linear_channel(channel: u8) -> f64 {
+    let c = channel as f64 / 255.0;
+    if c <= 0.03928 { c / 12.92 } else { ((c + 0.055) / 1.055).pow(&2.4) }
+}
+
+fn hue_to_rgb(m1: f64, m2: f64, h: f64) -> f64 {
+    let h = if h < 0.0 { h + 1.0 } else if h > 1.0 { h - 1.0 } else { h };
+    if h * 6.0 < 1.0 {
+        m1 + (m2 - m1) * h * 6.0
+    } else if h * 2.0 < 1.0 {
+        m2
+    } else if h * 3.0 < 2.0 {
+        m1 + (m2 - m1) * (2.0 / 3.0 - h) * 6.0
+    } else {
+        m1
+    }
+}
+
+fn parse_hex(digits: &str) -> CssResult<CssColor> {
+    let mut values = ~[];
+    for c in digits.iter() {
+        match c.to_digit(16) {
+            Some(value) => values.push(value as u8),
+            None => return Err(CssInvalid)
+        }
+    }
+    // The short forms repeat each digit
+    let short = |i: uint| values[i] * 17;
+    let long = |i: uint| values[i * 2] * 16 + values[i * 2 + 1];
+    match values.len() {
+        3 => Ok(CssColor::rgb(short(0), short(1), short(2))),
+        4 => Ok(CssColor::rgba(short(0), short(1), short(2), short(3))),
+        6 => Ok(CssColor::rgb(long(0), long(1), long(2))),
+        8 => Ok(CssColor::rgba(long(0), long(1), long(2), long(3))),
+        _ => Err(CssInvalid)
+    }
+}
+
+// The CSS 3 colour keywords, as 0xrrggbb
+static NAMED_COLORS: &'static [(&'static str, u32)] = &[
+    ("aliceblue", 0xf0f8ff),
+    ("antiquewhite", 0xfaebd7),
+    ("aqua", 0x00ffff),
+    ("aquamarine", 0x7fffd4),
+    ("azure", 0xf0ffff),
+    ("beige", 0xf5f5dc),
+    ("bisque", 0xffe4c4),
+    ("black", 0x000000),
+    ("blanchedalmond", 0xffebcd),
+    ("blue", 0x0000ff),
+    ("blueviolet", 0x8a2be2),
+    ("brown", 0xa52a2a),
+    ("burlywood", 0xdeb887),
+    ("cadetblue", 0x5f9ea0),
+    ("chartreuse", 0x7fff00),
+    ("chocolate", 0xd2691e),
+    ("coral", 0xff7f50),
+    ("cornflowerblue", 0x6495ed),
+    ("cornsilk", 0xfff8dc),
+    ("crimson", 0xdc143c),
+    ("cyan", 0x00ffff),
+    ("darkblue", 0x00008b),
+    ("darkcyan", 0x008b8b),
+    ("darkgoldenrod", 0xb8860b),
+    ("darkgray", 0xa9a9a9),
+    ("darkgreen", 0x006400),
+    ("darkgrey", 0xa9a9a9),
+    ("darkkhaki", 0xbdb76b),
+    ("darkmagenta", 0x8b008b),
+    ("darkolivegreen", 0x556b2f),
+    ("darkorange", 0xff8c00),
+    ("darkorchid", 0x9932cc),
+    ("darkred", 0x8b0000),
+    ("darksalmon", 0xe9967a),
+    ("darkseagreen", 0x8fbc8f),
+    ("darkslateblue", 0x483d8b),
+    ("darkslategray", 0x2f4f4f),
+    ("darkslategrey", 0x2f4f4f),
+    ("darkturquoise", 0x00ced1),
+    ("darkviolet", 0x9400d3),
+    ("deeppink", 0xff1493),
+    ("deepskyblue", 0x00bfff),
+    ("dimgray", 0x696969),
+    ("dimgrey", 0x696969),
+    ("dodgerblue", 0x1e90ff),
+    ("firebrick", 0xb22222),
+    ("floralwhite", 0xfffaf0),
+    ("forestgreen", 0x228b22),
+    ("fuchsia", 0xff00ff),
+    ("gainsboro", 0xdcdcdc),
+    ("ghostwhite", 0xf8f8ff),
+    ("gold", 0xffd700),
+    ("goldenrod", 0xdaa520),
+    ("gray", 0x808080),
+    ("green", 0x008000),
+    ("greenyellow", 0xadff2f),
+    ("grey", 0x808080),
+    ("honeydew", 0xf0fff0),
+    ("hotpink", 0xff69b4),
+    ("indianred", 0xcd5c5c),
+    ("indigo", 0x4b0082),
+    ("ivory", 0xfffff0),
+    ("khaki", 0xf0e68c),
+    ("lavender", 0xe6e6fa),
+    ("lavenderblush", 0xfff0f5),
+    ("lawngreen", 0x7cfc00),
+    ("lemonchiffon", 0xfffacd),
+    ("lightblue", 0xadd8e6),
+    ("lightcoral", 0xf08080),
+    ("lightcyan", 0xe0ffff),
+    ("lightgoldenrodyellow", 0xfafad2),
+    ("lightgray", 0xd3d3d3),
+    ("lightgreen", 0x90ee90),
+    ("lightgrey", 0xd3d3d3),
+    ("lightpink", 0xffb6c1),
+    ("lightsalmon", 0xffa07a),
+    ("lightseagreen", 0x20b2aa),
+    ("lightskyblue", 0x87cefa),
+    ("lightslategray", 0x778899),
+    ("lightslategrey", 0x778899),
+    ("lightsteelblue", 0xb0c4de),
+    ("lightyellow", 0xffffe0),
+    ("lime", 0x00ff00),
+    ("limegreen", 0x32cd32),
+    ("linen", 0xfaf0e6),
+    ("magenta", 0xff00ff),
+    ("maroon", 0x800000),
+    ("mediumaquamarine", 0x66cdaa),
+    ("mediumblue", 0x0000cd),
+    ("mediumorchid", 0xba55d3),
+    ("mediumpurple", 0x9370db),
+    ("mediumseagreen", 0x3cb371),
+    ("mediumslateblue", 0x7b68ee),
+    ("mediumspringgreen", 0x00fa9a),
+    ("mediumturquoise", 0x48d1cc),
+    ("mediumvioletred", 0xc71585),
+    ("midnightblue", 0x191970),
+    ("mintcream", 0xf5fffa),
+    ("mistyrose", 0xffe4e1),
+    ("moccasin", 0xffe4b5),
+    ("navajowhite", 0xffdead),
+    ("navy", 0x000080),
+    ("oldlace", 0xfdf5e6),
+    ("olive", 0x808000),
+    ("olivedrab", 0x6b8e23),
+    ("orange", 0xffa500),
+    ("orangered", 0xff4500),
+    ("orchid", 0xda70d6),
+    ("palegoldenrod", 0xeee8aa),
+    ("palegreen", 0x98fb98),
+    ("paleturquoise", 0xafeeee),
+    ("palevioletred", 0xdb7093),
+    ("papayawhip", 0xffefd5),
+    ("peachpuff", 0xffdab9),
+    ("peru", 0xcd853f),
+    ("pink", 0xffc0cb),
+    ("plum", 0xdda0dd),
+    ("powderblue", 0xb0e0e6),
+    ("purple", 0x800080),
+    ("red", 0xff0000),
+    ("rosybrown", 0xbc8f8f),
+    ("royalblue", 0x4169e1),
+    ("saddlebrown", 0x8b4513),
+    ("salmon", 0xfa8072),
+    ("sandybrown", 0xf4a460),
+    ("seagreen", 0x2e8b57),
+    ("seashell", 0xfff5ee),
+    ("sienna", 0xa0522d),
+    ("silver", 0xc0c0c0),
+    ("skyblue", 0x87ceeb),
+    ("slateblue", 0x6a5acd),
+    ("slategray", 0x708090),
+    ("slategrey", 0x708090),
+    ("snow", 0xfffafa),
+    ("springgreen", 0x00ff7f),
+    ("steelblue", 0x4682b4),
+    ("tan", 0xd2b48c),
+    ("teal", 0x008080),
+    ("thistle", 0xd8bfd8),
+    ("tomato", 0xff6347),
+    ("turquoise", 0x40e0d0),
+    ("violet", 0xee82ee),
+    ("wheat", 0xf5deb3),
+    ("white", 0xffffff),
+    ("whitesmoke", 0xf5f5f5),
+    ("yellow", 0xffff00),
+    ("yellowgreen", 0x9acd32),
+];
@@ -0,0 +1,113 @@
+// Copyright 2013 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+/*!
+
+Checks that text stands out enough from what it is drawn on, following the
+WCAG 2 contrast criteria.
+
+A node's own background is often transparent, so what the text is really
+drawn on is found by painting the backgrounds of its ancestors, from the
+canvas up, over each other.
+
+*/
+
+use types::CssColor;
+use computed::CssComputedStyle;
+use values::{CssColorColor, CssColorInherit, CssFontWeightValue};
+use values::{CssFontWeightBold, CssFontWeightBolder, CssFontWeight700, CssFontWeight800, CssFontWeight900};
+use fixed::CssFixed;
+
+/// How text fares against its effective background
+pub struct CssContrastReport {
+    // The text colour as painted, over the background
+    foreground: CssColor,
+    background: CssColor,
+    ratio: f64,
+    // Large text only needs 3:1 for AA and 4.5:1 for AAA
+    large_text: bool,
+    aa: bool,
+    aaa: bool
+}
+
+/// What `style`'s content is drawn on. `ancestors` are the styles of the
+/// node's ancestors, parent first, and `canvas` is what is behind them all
+pub fn effective_background(style: &CssComputedStyle,
+                            ancestors: &[CssComputedStyle],
+                            canvas: &CssColor) -> CssColor {
+    // Nothing below an opaque background shows through
+    let mut layers = ~[background_of(style)];
+    for ancestor in ancestors.iter() {
+        if layers[layers.len() - 1].is_opaque() {
+            break;
+        }
+        layers.push(background_of(ancestor));
+    }
+    let mut background = *canvas;
+    for layer in layers.rev_iter() {
+        background = layer.over(&background);
+    }
+    background
+}
+
+/// Checks `style`'s text colour for text of `font_size` pixels and
+/// `font_weight`, which are usually the style's own
+pub fn check_contrast(style: &CssComputedStyle,
+                      ancestors: &[CssComputedStyle],
+                      canvas: &CssColor,
+                      font_size: CssFixed,
+                      font_weight: CssFontWeightValue) -> CssContrastReport {
+    let background = effective_background(style, ancestors, canvas);
+    let foreground = text_color(style, ancestors).over(&background);
+    let ratio = foreground.contrast_ratio(&background);
+    let large_text = is_large_text(font_size, font_weight);
+    let (aa, aaa) = if large_text { (3.0, 4.5) } else { (4.5, 7.0) };
+    CssContrastReport {
+        foreground: foreground,
+        background: background,
+        ratio: ratio,
+        large_text: large_text,
+        aa: ratio >= aa,
+        aaa: ratio >= aaa
+    }
+}
+
+// At least 18pt, or 14pt when bold
+fn is_large_text(font_size: CssFixed, font_weight: CssFontWeightValue) -> bool {
+    let bold = match font_weight {
+        CssFontWeightBold | CssFontWeightBolder |
+        CssFontWeight700 | CssFontWeight800 | CssFontWeight900 => true,
+        _ => false
+    };
+    let minimum = if bold { 14.0 } else { 18.0 };
+    // 3px to every 4pt
+    font_size >= CssFixed::from_f64(minimum * 4.0 / 3.0)
+}
+
+fn background_of(style: &CssComputedStyle) -> CssColor {
+    match style.background_color() {
+        CssColorColor(color) => color,
+        CssColorInherit => CssColor::rgba(0, 0, 0, 0)
+    }
+}
+
+// The nearest colour that is set, black if none is
+fn text_color(style: &CssComputedStyle, ancestors: &[CssComputedStyle]) -> CssColor {
+    match style.color() {
+        CssColorColor(color) => return color,
+        CssColorInherit => ()
+    }
+    for ancestor in ancestors.iter() {
+        match ancestor.color() {
+            CssColorColor(color) => return color,
+            CssColorInherit => ()
+        }
+    }
+    CssColor::rgb(0, 0, 0)
+}
@@ -10,21 +10,28 @@
 use wapcaplet::LwcString;
 use wapcaplet::ll::{lwc_string, rust_lwc_string_ref};
 use std::libc::{c_void, c_char};
-use std::libc::types::common::c99::{uint32_t};
+use std::str;
+use std::libc::types::common::c99::{uint32_t, uint64_t};
 use std::cast::transmute;
 use std::ptr::null;
 use ll::{c_enum, rust_enum};
 use ll_lwcstr_to_hl_lwcstr = wapcaplet::from_lwc_string;
-use ll::types::{css_language_level, CSS_LEVEL_1, CSS_LEVEL_2, CSS_LEVEL_21, CSS_LEVEL_3, CSS_LEVEL_DEFAULT};
-use types::{CssLanguageLevel, CssLevel1, CssLevel2, CssLevel21, CssLevel3, CssLevelDefault, CssLevelNotACLikeEnum};
+use lwcstr_from_rust_str = wapcaplet::from_rust_string;
+use ll::types::{css_language_level, CSS_LEVEL_1, CSS_LEVEL_2, CSS_LEVEL_21, CSS_LEVEL_3};
+use types::{CssLanguageLevel, CssLevel1, CssLevel2, CssLevel21, CssLevel3};
 use ll::errors::{css_error, CSS_OK};
 use errors::CssError;
-use ll::properties::css_font_family_e;
+use ll::properties::{css_font_family_e, css_font_style_e, css_font_variant_e, css_font_weight_e};
 use properties::CssFontFamily;
-use ll::types::{css_color, css_unit, css_qname};
+use ll::types::{css_color, css_unit, css_qname, css_origin};
+use ll::types::{CSS_ORIGIN_UA, CSS_ORIGIN_USER, CSS_ORIGIN_AUTHOR};
 use types::{CssColor, CssUnit, CssQName};
-use ll::stylesheet::{css_fixed, css_stylesheet_params};
-use stylesheet::{CssStylesheetParams};
+use types::{CssOrigin, CssOriginUserAgent, CssOriginUser, CssOriginAuthor};
+use ll::stylesheet::{css_fixed, css_stylesheet, css_stylesheet_params, css_system_font};
+use fixed::CssFixed;
+use stylesheet::{CssStylesheetParams, CssUrlResolutionFn, CssImportNotificationFn};
+use stylesheet::{CssColorResolutionFn, CssFontResolutionFn};
+use media::CssMedia;
 use ll::select::{css_pseudo_element};
 use select::{CssPseudoElement};
 use std::mem;
@@ -45,8 +52,27 @@ impl ToLl<css_language_level> for CssLanguageLevel {
             CssLevel2 => CSS_LEVEL_2,
             CssLevel21 => CSS_LEVEL_21,
             CssLevel3 => CSS_LEVEL_3,
-            CssLevelDefault => CSS_LEVEL_DEFAULT,
-            CssLevelNotACLikeEnum(*) => fail!()
+        }
+    }
+}
+
+pub fn ll_language_level_to_hl_language_level(level: css_language_level) -> CssLanguageLevel {
+    match level {
+        CSS_LEVEL_1 => CssLevel1,
+        CSS_LEVEL_2 => CssLevel2,
+        CSS_LEVEL_21 => CssLevel21,
+        CSS_LEVEL_3 => CssLevel3,
+        _ => fail!(fmt!("unknown css_language_level %?", level))
+    }
+}
+
+impl ToLl<css_origin> for CssOrigin {
+    #[inline]
+    fn to_ll(&self) -> css_origin {
+        match *self {
+            CssOriginUserAgent => CSS_ORIGIN_UA,
+            CssOriginUser => CSS_ORIGIN_USER,
+            CssOriginAuthor => CSS_ORIGIN_AUTHOR
         }
     }
 }
@@ -65,18 +91,23 @@ impl ToLl<css_font_family_e> for CssFontFamily {
     }
 }
 
+// css_color is 0xAARRGGBB in native byte order
 impl ToLl<css_color> for CssColor {
     #[inline]
     fn to_ll(&self) -> css_color {
-        assert!(mem::size_of::<CssColor>() == mem::size_of::<css_color>());
-        unsafe { transmute(*self) }
+        (self.a as css_color << 24) | (self.r as css_color << 16) |
+            (self.g as css_color << 8) | (self.b as css_color)
     }
 }
 
 #[inline]
 pub fn ll_color_to_hl_color(color: css_color) -> CssColor {
-    assert!(mem::size_of::<CssColor>() == mem::size_of::<css_color>());
-    unsafe { transmute(color) }
+    CssColor {
+        r: (color >> 16) as u8,
+        g: (color >> 8) as u8,
+        b: color as u8,
+        a: (color >> 24) as u8
+    }
 }
 
 impl ToLl<(css_unit, css_fixed)> for CssUnit {
@@ -85,22 +116,22 @@ impl ToLl<(css_unit, css_fixed)> for CssUnit {
         use ll::types::*;
         use types::*;
         match *self {
-            CssUnitPx(value) => (CSS_UNIT_PX, value),
-            CssUnitEx(value) => (CSS_UNIT_EX, value),
-            CssUnitEm(value) => (CSS_UNIT_EM, value),
-            CssUnitIn(value) => (CSS_UNIT_IN, value),
-            CssUnitCm(value) => (CSS_UNIT_CM, value),
-            CssUnitMm(value) => (CSS_UNIT_MM, value),
-            CssUnitPt(value) => (CSS_UNIT_PT, value),
-            CssUnitPc(value) => (CSS_UNIT_PC, value),
-            CssUnitPct(value) => (CSS_UNIT_PCT, value),
-            CssUnitDeg(value) => (CSS_UNIT_DEG, value),
-            CssUnitGrad(value) => (CSS_UNIT_GRAD, value),
-            CssUnitRad(value) => (CSS_UNIT_RAD, value),
-            CssUnitMs(value) => (CSS_UNIT_MS, value),
-            CssUnitS(value) => (CSS_UNIT_S, value),
-            CssUnitHz(value) => (CSS_UNIT_HZ, value),
-            CssUnitKHz(value) => (CSS_UNIT_KHZ, value)
+            CssUnitPx(value) => (CSS_UNIT_PX, value.to_raw()),
+            CssUnitEx(value) => (CSS_UNIT_EX, value.to_raw()),
+            CssUnitEm(value) => (CSS_UNIT_EM, value.to_raw()),
+            CssUnitIn(value) => (CSS_UNIT_IN, value.to_raw()),
+            CssUnitCm(value) => (CSS_UNIT_CM, value.to_raw()),
+            CssUnitMm(value) => (CSS_UNIT_MM, value.to_raw()),
+            CssUnitPt(value) => (CSS_UNIT_PT, value.to_raw()),
+            CssUnitPc(value) => (CSS_UNIT_PC, value.to_raw()),
+            CssUnitPct(value) => (CSS_UNIT_PCT, value.to_raw()),
+            CssUnitDeg(value) => (CSS_UNIT_DEG, value.to_raw()),
+            CssUnitGrad(value) => (CSS_UNIT_GRAD, value.to_raw()),
+            CssUnitRad(value) => (CSS_UNIT_RAD, value.to_raw()),
+            CssUnitMs(value) => (CSS_UNIT_MS, value.to_raw()),
+            CssUnitS(value) => (CSS_UNIT_S, value.to_raw()),
+            CssUnitHz(value) => (CSS_UNIT_HZ, value.to_raw()),
+            CssUnitKHz(value) => (CSS_UNIT_KHZ, value.to_raw())
         }
     }
 }
@@ -109,6 +140,7 @@ impl ToLl<(css_unit, css_fixed)> for CssUnit {
 pub fn ll_unit_to_hl_unit(unit: css_unit, value: css_fixed) -> CssUnit {
     use ll::types::*;
     use types::*;
+    let value = CssFixed::from_raw(value);
     if unit == CSS_UNIT_PX {
         CssUnitPx(value)
     } else if unit == CSS_UNIT_EX {
@@ -179,40 +211,128 @@ pub fn c_enum_to_rust_enum<T>(val: c_enum) -> T {
 
 impl AsLl<css_stylesheet_params> for CssStylesheetParams {
     fn as_ll<U>(&self, f: &fn(&css_stylesheet_params) -> U) -> U {
-        do self.charset.to_c_str().with_ref |charset| {
-            do self.url.to_c_str().with_ref |url| {
-                do self.title.to_c_str().with_ref |title| {
-                    let params = css_stylesheet_params {
-                        params_version: self.params_version as uint32_t,
-                        level: self.level.to_ll(),
-                        charset: charset,
-                        url: url,
-                        title: title,
-                        allow_quirks: self.allow_quirks,
-                        inline_style: self.inline_style,
-                        resolve: resolve,
-                        resolve_pw: unsafe { transmute(&self.resolve) },
-                        import: unsafe { transmute(0) },
-                        import_pw: null(),
-                        color: unsafe { transmute(0) },
-                        color_pw: null(),
-                        font: unsafe { transmute(0) },
-                        font_pw: null()
-                    };
-                    f(&params)
-                }
+        params_as_ll(self, self.get_charset(), f)
+    }
+}
+
+// Like as_ll, but with the charset overridden. A null charset tells libcss to detect it
+pub fn params_as_ll<U>(params: &CssStylesheetParams,
+                       charset: Option<&str>,
+                       f: &fn(&css_stylesheet_params) -> U) -> U {
+    do with_opt_c_str(charset) |charset| {
+        do params.get_url().to_c_str().with_ref |url| {
+            do params.get_title().to_c_str().with_ref |title| {
+                let ll_params = css_stylesheet_params {
+                    params_version: params.get_params_version() as uint32_t,
+                    level: params.get_level().to_ll(),
+                    charset: charset,
+                    url: url,
+                    title: title,
+                    allow_quirks: params.allows_quirks(),
+                    inline_style: params.is_inline_style(),
+                    // With no resolver the URLs are kept as they are
+                    resolve: resolve,
+                    resolve_pw: callback_pw(params.get_resolver()),
+                    import: match params.get_importer() {
+                        Some(_) => notify_import,
+                        None => unsafe { transmute(0) }
+                    },
+                    import_pw: callback_pw(params.get_importer()),
+                    color: match params.get_color_resolver() {
+                        Some(_) => resolve_color,
+                        None => unsafe { transmute(0) }
+                    },
+                    color_pw: callback_pw(params.get_color_resolver()),
+                    font: match params.get_font_resolver() {
+                        Some(_) => resolve_font,
+                        None => unsafe { transmute(0) }
+                    },
+                    font_pw: callback_pw(params.get_font_resolver())
+                };
+                f(&ll_params)
             }
         }
     }
 }
 
-extern fn resolve(_pw: *c_void, _base: *c_char, rel: *lwc_string, abs: *mut *lwc_string) -> css_error {
+fn with_opt_c_str<U>(s: Option<&str>, f: &fn(*c_char) -> U) -> U {
+    match s {
+        Some(s) => do s.to_c_str().with_ref |s| { f(s) },
+        None => f(null())
+    }
+}
+
+// The callbacks live in Arcs that the sheet keeps, so their addresses
+// stay good for as long as libcss can call them
+fn callback_pw<T>(callback: Option<&T>) -> *c_void {
+    match callback {
+        Some(callback) => unsafe { transmute(callback) },
+        None => null()
+    }
+}
+
+extern fn resolve(pw: *c_void, base: *c_char, rel: *lwc_string, abs: *mut *lwc_string) -> css_error {
     unsafe {
-        // TODO
-        rust_lwc_string_ref(rel);
-        *abs = rel;
+        if pw.is_null() {
+            rust_lwc_string_ref(rel);
+            *abs = rel;
+            return CSS_OK;
+        }
+        let resolver: &CssUrlResolutionFn = transmute(pw);
+        let base = str::raw::from_c_str(base);
+        match (*resolver)(base, &ll_lwcstr_to_hl_lwcstr(rel)) {
+            Ok(url) => {
+                *abs = url.raw_reffed();
+                CSS_OK
+            }
+            Err(e) => e.to_ll()
+        }
+    }
+}
+
+extern fn notify_import(pw: *c_void, _parent: *css_stylesheet, url: *lwc_string,
+                        media: *uint64_t) -> css_error {
+    unsafe {
+        let importer: &CssImportNotificationFn = transmute(pw);
+        match (*importer)(&ll_lwcstr_to_hl_lwcstr(url), CssMedia::from_ll(*media)) {
+            Ok(()) => CSS_OK,
+            Err(e) => e.to_ll()
+        }
+    }
+}
+
+extern fn resolve_color(pw: *c_void, name: *lwc_string, color: *mut css_color) -> css_error {
+    unsafe {
+        let resolver: &CssColorResolutionFn = transmute(pw);
+        match (*resolver)(&ll_lwcstr_to_hl_lwcstr(name)) {
+            Ok(c) => {
+                *color = c.to_ll();
+                CSS_OK
+            }
+            Err(e) => e.to_ll()
+        }
+    }
+}
+
+extern fn resolve_font(pw: *c_void, name: *lwc_string, system_font: *mut css_system_font) -> css_error {
+    unsafe {
+        let resolver: &CssFontResolutionFn = transmute(pw);
+        match (*resolver)(&ll_lwcstr_to_hl_lwcstr(name)) {
+            Ok(font) => {
+                *system_font = css_system_font {
+                    style: font.style as css_font_style_e,
+                    variant: font.variant as css_font_variant_e,
+                    weight: font.weight as css_font_weight_e,
+                    size: font.size,
+                    line_height: font.line_height,
+                    // libcss takes over this reference
+                    family: lwcstr_from_rust_str(font.family.as_slice()).raw_reffed()
+                };
+                CSS_OK
+            }
+            Err(e) => e.to_ll()
+        }
     }
-    CSS_OK
 }
 
 pub fn write_ll_qname(hlqname: &mut CssQName, llqname: *mut css_qname) {
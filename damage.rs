@@ -0,0 +1,90 @@
+// Copyright 2013 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+/*!
+
+Works out how much layout work a style change needs, by comparing every
+property a `CssComputedStyle` exposes before and after a restyle.
+
+*/
+
+use computed::CssComputedStyle;
+
+/// What has to be redone for a node whose style changed. Ordered from least
+/// to most work, and each kind includes the ones before it
+#[deriving(Eq, Ord, Clone)]
+pub enum RestyleDamage {
+    RestyleNone,
+    // Only how the box is drawn changed
+    RestyleRepaint,
+    // The size or position of the box, or of its text, may have changed
+    RestyleReflow
+}
+
+impl RestyleDamage {
+    /// The damage needing the work of both
+    pub fn union(self, other: RestyleDamage) -> RestyleDamage {
+        if other > self { other } else { self }
+    }
+}
+
+pub fn diff(old: &CssComputedStyle, new: &CssComputedStyle) -> RestyleDamage {
+    if needs_reflow(old, new) {
+        RestyleReflow
+    } else if needs_repaint(old, new) {
+        RestyleRepaint
+    } else {
+        RestyleNone
+    }
+}
+
+fn needs_reflow(old: &CssComputedStyle, new: &CssComputedStyle) -> bool {
+    // Whether the node is the root only changes how display is fixed up,
+    // which is the same on both sides
+    old.display(false) != new.display(false)
+        || old.position() != new.position()
+        || old.float() != new.float()
+        || old.clear() != new.clear()
+        || old.width() != new.width()
+        || old.height() != new.height()
+        || old.margin_top() != new.margin_top()
+        || old.margin_right() != new.margin_right()
+        || old.margin_bottom() != new.margin_bottom()
+        || old.margin_left() != new.margin_left()
+        || old.padding_top() != new.padding_top()
+        || old.padding_right() != new.padding_right()
+        || old.padding_bottom() != new.padding_bottom()
+        || old.padding_left() != new.padding_left()
+        // A border's style decides whether its width takes up space
+        || old.border_top_style() != new.border_top_style()
+        || old.border_right_style() != new.border_right_style()
+        || old.border_bottom_style() != new.border_bottom_style()
+        || old.border_left_style() != new.border_left_style()
+        || old.border_top_width() != new.border_top_width()
+        || old.border_right_width() != new.border_right_width()
+        || old.border_bottom_width() != new.border_bottom_width()
+        || old.border_left_width() != new.border_left_width()
+        || old.font_family() != new.font_family()
+        || old.font_size() != new.font_size()
+        || old.font_style() != new.font_style()
+        || old.font_weight() != new.font_weight()
+        || old.line_height() != new.line_height()
+        || old.vertical_align() != new.vertical_align()
+        || old.text_align() != new.text_align()
+}
+
+fn needs_repaint(old: &CssComputedStyle, new: &CssComputedStyle) -> bool {
+    old.color() != new.color()
+        || old.background_color() != new.background_color()
+        || old.border_top_color() != new.border_top_color()
+        || old.border_right_color() != new.border_right_color()
+        || old.border_bottom_color() != new.border_bottom_color()
+        || old.border_left_color() != new.border_left_color()
+        || old.text_decoration() != new.text_decoration()
+}
@@ -0,0 +1,186 @@
+// Copyright 2013 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+/*!
+
+Parse error diagnostics.
+
+libcss recovers from syntax errors the way CSS 2.1 section 4.2 says to, by
+dropping the declaration or rule, and reports nothing. The parser in
+`parser.rs` follows the same recovery rules and records here what gets
+dropped and why, for a developer console.
+
+The checks here report syntax errors, unknown properties and unknown
+at-rules. A declaration whose value libcss rejects for its property, like
+`color: 12px`, takes libcss to find, so only the diagnostics a
+`CssStylesheet` collects as it is parsed include those.
+
+*/
+
+use std::ascii::StrAsciiExt;
+use tokenizer::*;
+use parser::{parse_stylesheet, parse_declarations, token_at};
+
+#[deriving(Eq, Clone)]
+pub enum CssSeverity {
+    // Something was skipped that may be valid for another user agent
+    CssSeverityWarning,
+    CssSeverityError
+}
+
+#[deriving(Eq, Clone)]
+pub enum CssDropped {
+    CssDroppedNothing,
+    // The property name of the dropped declaration
+    CssDroppedDeclaration(~str),
+    // The selector or at-rule prelude of the dropped rule
+    CssDroppedRule(~str)
+}
+
+#[deriving(Eq, Clone)]
+pub struct CssDiagnostic {
+    line: uint,
+    column: uint,
+    // The offending token as written, or empty at the end of the sheet
+    token: ~str,
+    message: ~str,
+    dropped: CssDropped,
+    severity: CssSeverity
+}
+
+/// Diagnoses every error in the text of a whole sheet
+pub fn check_stylesheet(text: &str) -> ~[CssDiagnostic] {
+    let (_, diagnostics) = parse_stylesheet(text);
+    diagnostics
+}
+
+/// Diagnoses a list of declarations without braces, like a style attribute
+pub fn check_declarations(text: &str) -> ~[CssDiagnostic] {
+    let (_, diagnostics) = parse_declarations(text);
+    diagnostics
+}
+
+static CSS3_PSEUDO_CLASSES: &'static [&'static str] = &[
+    "checked", "disabled", "empty", "enabled", "first-of-type", "last-child", "last-of-type",
+    "not", "nth-child", "nth-last-child", "nth-last-of-type", "nth-of-type", "only-child",
+    "only-of-type", "root", "target"
+];
+
+static CSS3_PROPERTIES: &'static [&'static str] = &[
+    "break-after", "break-before", "break-inside", "column-count", "column-fill", "column-gap",
+    "column-rule", "column-rule-color", "column-rule-style", "column-rule-width", "column-span",
+    "column-width", "columns", "opacity"
+];
+
+static CSS3_FUNCTIONS: &'static [&'static str] = &["hsl", "hsla", "rgba"];
+
+static CSS3_UNITS: &'static [&'static str] = &["ch", "rem", "vh", "vmax", "vmin", "vw"];
+
+/// Finds the constructs in a sheet that only CSS3 has: selectors, properties,
+/// color functions, units, media features and @namespace. Each is a warning
+/// that drops nothing, since libcss decides what to do with it
+pub fn check_level(text: &str, inline_style: bool) -> ~[CssDiagnostic] {
+    let tokens: ~[(Token, SourcePosition)] = Tokenizer::new(text).filter(|&(ref token, _)| {
+        match *token { TokenComment(_) => false, _ => true }
+    }).collect();
+    let mut diagnostics = ~[];
+    let report = |diagnostics: &mut ~[CssDiagnostic], index: uint, what: ~str| {
+        let (ref token, position) = tokens[index];
+        diagnostics.push(CssDiagnostic {
+            line: position.line,
+            column: position.column,
+            token: token.to_css(),
+            message: fmt!("%s is only in CSS3", what),
+            dropped: CssDroppedNothing,
+            severity: CssSeverityWarning
+        });
+    };
+    let contains = |list: &'static [&'static str], name: &~str| {
+        let name = name.to_ascii_lower();
+        list.iter().any(|s| *s == name.as_slice())
+    };
+
+    // For each open block, whether it holds declarations
+    let mut blocks: ~[bool] = ~[];
+    // The at-rule whose prelude is being read, if any
+    let mut at_rule: Option<~str> = None;
+    // Whether the next identifier in a declaration block is a property name
+    let mut expect_property = true;
+
+    for i in range(0, tokens.len()) {
+        let in_declarations = match blocks.last_opt() {
+            Some(&declarations) => declarations,
+            None => inline_style
+        };
+        let (ref token, _) = tokens[i];
+        let next = token_at(tokens, i + 1);
+
+        if in_declarations {
+            match *token {
+                TokenIdent(ref name) if expect_property => {
+                    if contains(CSS3_PROPERTIES, name) {
+                        report(&mut diagnostics, i, fmt!("The '%s' property", *name));
+                    }
+                    expect_property = false;
+                }
+                TokenFunction(ref name) if contains(CSS3_FUNCTIONS, name) => {
+                    report(&mut diagnostics, i, fmt!("The %s() color function", *name));
+                }
+                TokenDimension(_, ref unit) if contains(CSS3_UNITS, unit) => {
+                    report(&mut diagnostics, i, fmt!("The '%s' unit", *unit));
+                }
+                TokenSemicolon => expect_property = true,
+                TokenCloseBrace => {
+                    blocks.pop();
+                    expect_property = true;
+                }
+                _ => ()
+            }
+            continue;
+        }
+
+        match *token {
+            TokenAtKeyword(ref name) if at_rule.is_none() => {
+                let name = name.to_ascii_lower();
+                if name.as_slice() == "namespace" {
+                    report(&mut diagnostics, i, ~"@namespace");
+                }
+                at_rule = Some(name);
+            }
+            TokenOpenBrace => {
+                // Only @media holds rules; rulesets, @page and @font-face hold declarations
+                blocks.push(at_rule != Some(~"media"));
+                at_rule = None;
+                expect_property = true;
+            }
+            TokenSemicolon => at_rule = None,
+            TokenCloseBrace => { blocks.pop(); }
+            TokenOpenParen if at_rule == Some(~"media") || at_rule == Some(~"import") => {
+                report(&mut diagnostics, i, ~"A media feature");
+            }
+            _ if at_rule.is_some() => (),
+            TokenPrefixMatch | TokenSuffixMatch | TokenSubstringMatch => {
+                report(&mut diagnostics, i, fmt!("The '%s' attribute selector", token.to_css()));
+            }
+            TokenDelim('~') => report(&mut diagnostics, i, ~"The '~' combinator"),
+            TokenColon => {
+                match next {
+                    Some(&TokenColon) => report(&mut diagnostics, i, ~"The '::' pseudo-element syntax"),
+                    Some(&TokenIdent(ref name)) | Some(&TokenFunction(ref name))
+                            if contains(CSS3_PSEUDO_CLASSES, name) => {
+                        report(&mut diagnostics, i + 1, fmt!("The ':%s' pseudo-class", name.to_ascii_lower()));
+                    }
+                    _ => ()
+                }
+            }
+            _ => ()
+        }
+    }
+    diagnostics
+}
@@ -0,0 +1,147 @@
+// Copyright 2013 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+/*!
+
+The fixed-point numbers libcss computes with: 32 bits, 10 of them after the
+point. Arithmetic and conversions round to the nearest representable value,
+halves away from zero, and saturate instead of overflowing.
+
+*/
+
+use ll::stylesheet::css_fixed;
+use std::i32;
+
+static FRACTION_BITS: uint = 10;
+static ONE: i64 = 1 << FRACTION_BITS;
+
+#[deriving(Eq, Ord, Clone)]
+pub struct CssFixed(css_fixed);
+
+impl CssFixed {
+    /// From libcss's representation, scaled by 1024
+    #[inline]
+    pub fn from_raw(value: css_fixed) -> CssFixed {
+        CssFixed(value)
+    }
+
+    #[inline]
+    pub fn to_raw(&self) -> css_fixed {
+        **self
+    }
+
+    pub fn from_int(value: i32) -> CssFixed {
+        saturate(value as i64 * ONE)
+    }
+
+    /// NaN becomes zero
+    pub fn from_f64(value: f64) -> CssFixed {
+        if value != value {
+            return CssFixed(0);
+        }
+        let scaled = value * (ONE as f64);
+        if scaled >= i32::max_value as f64 {
+            CssFixed(i32::max_value)
+        } else if scaled <= i32::min_value as f64 {
+            CssFixed(i32::min_value)
+        } else if scaled < 0.0 {
+            CssFixed((scaled - 0.5) as css_fixed)
+        } else {
+            CssFixed((scaled + 0.5) as css_fixed)
+        }
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        (**self as f64) / (ONE as f64)
+    }
+
+    /// `self * numerator / denominator`, rounded once at the end so that
+    /// scaling by a ratio loses no more precision than it has to
+    pub fn mul_div(&self, numerator: i64, denominator: i64) -> CssFixed {
+        saturate(div_round(**self as i64 * numerator, denominator))
+    }
+
+    /// Rounded to the nearest integer
+    pub fn to_int(&self) -> i32 {
+        div_round(**self as i64, ONE) as i32
+    }
+}
+
+// n / d to the nearest integer, halves away from zero
+fn div_round(n: i64, d: i64) -> i64 {
+    if (n < 0) != (d < 0) {
+        (n - d / 2) / d
+    } else {
+        (n + d / 2) / d
+    }
+}
+
+fn saturate(value: i64) -> CssFixed {
+    if value > i32::max_value as i64 {
+        CssFixed(i32::max_value)
+    } else if value < i32::min_value as i64 {
+        CssFixed(i32::min_value)
+    } else {
+        CssFixed(value as css_fixed)
+    }
+}
+
+impl Add<CssFixed, CssFixed> for CssFixed {
+    fn add(&self, other: &CssFixed) -> CssFixed {
+        saturate(**self as i64 + **other as i64)
+    }
+}
+
+impl Sub<CssFixed, CssFixed> for CssFixed {
+    fn sub(&self, other: &CssFixed) -> CssFixed {
+        saturate(**self as i64 - **other as i64)
+    }
+}
+
+impl Mul<CssFixed, CssFixed> for CssFixed {
+    fn mul(&self, other: &CssFixed) -> CssFixed {
+        self.mul_div(**other as i64, ONE)
+    }
+}
+
+impl Div<CssFixed, CssFixed> for CssFixed {
+    /// Dividing by zero saturates, except zero by zero which is zero
+    fn div(&self, other: &CssFixed) -> CssFixed {
+        if **other == 0 {
+            saturate(**self as i64 * (i32::max_value as i64 + 1))
+        } else {
+            self.mul_div(ONE, **other as i64)
+        }
+    }
+}
+
+impl Neg<CssFixed> for CssFixed {
+    fn neg(&self) -> CssFixed {
+        saturate(-(**self as i64))
+    }
+}
+
+impl ToStr for CssFixed {
+    /// Up to three decimal places, without trailing zeros
+    fn to_str(&self) -> ~str {
+        let thousandths = div_round(**self as i64 * 1000, ONE);
+        let sign = if thousandths < 0 { "-" } else { "" };
+        let magnitude = if thousandths < 0 { -thousandths } else { thousandths };
+        let (whole, fraction) = (magnitude / 1000, magnitude % 1000);
+        if fraction == 0 {
+            fmt!("%s%d", sign, whole as int)
+        } else {
+            let mut digits = fmt!("%03d", fraction as int);
+            while digits.ends_with("0") {
+                digits.pop_char();
+            }
+            fmt!("%s%d.%s", sign, whole as int, digits)
+        }
+    }
+}
@@ -0,0 +1,160 @@
+// Copyright 2013 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+/*!
+
+Resolves lengths to pixels. Results are rounded to the nearest 1/1024th of
+a pixel, halves away from zero.
+
+Values without a length to give, like `auto`, `inherit` and keywords whose
+size is up to the user agent, resolve to `None`.
+
+*/
+
+use types::*;
+use values::*;
+use fixed::CssFixed;
+
+static FIXED_ONE: i64 = 1024;
+
+/// What relative lengths are resolved against, all in pixels except `dpi`
+#[deriving(Eq, Clone)]
+pub struct LengthContext {
+    // What em is relative to: the element's font size, or the parent's
+    // when resolving font-size itself
+    font_size: CssFixed,
+    x_height: CssFixed,
+    // Pixels to the inch
+    dpi: CssFixed,
+    // What % is relative to, like the containing block's width
+    percentage_basis: CssFixed
+}
+
+impl LengthContext {
+    /// A context at 96 pixels to the inch with an x-height of half an em
+    pub fn new(font_size: CssFixed, percentage_basis: CssFixed) -> LengthContext {
+        LengthContext {
+            font_size: font_size,
+            x_height: font_size / CssFixed::from_int(2),
+            dpi: CssFixed::from_int(96),
+            percentage_basis: percentage_basis
+        }
+    }
+
+    pub fn x_height(self, x_height: CssFixed) -> LengthContext {
+        LengthContext { x_height: x_height, .. self }
+    }
+
+    pub fn dpi(self, dpi: CssFixed) -> LengthContext {
+        LengthContext { dpi: dpi, .. self }
+    }
+}
+
+impl CssUnit {
+    /// The length in pixels. Angles, times and frequencies have none
+    pub fn to_px(&self, ctx: &LengthContext) -> Option<CssFixed> {
+        let dpi = ctx.dpi.to_raw() as i64;
+        match *self {
+            CssUnitPx(value) => Some(value),
+            CssUnitEm(value) => Some(value.mul_div(ctx.font_size.to_raw() as i64, FIXED_ONE)),
+            CssUnitEx(value) => Some(value.mul_div(ctx.x_height.to_raw() as i64, FIXED_ONE)),
+            CssUnitPct(value) => Some(value.mul_div(ctx.percentage_basis.to_raw() as i64, 100 * FIXED_ONE)),
+            CssUnitIn(value) => Some(value.mul_div(dpi, FIXED_ONE)),
+            // 2.54cm to the inch
+            CssUnitCm(value) => Some(value.mul_div(dpi * 100, 254 * FIXED_ONE)),
+            CssUnitMm(value) => Some(value.mul_div(dpi * 10, 254 * FIXED_ONE)),
+            // 72pt and 6pc to the inch
+            CssUnitPt(value) => Some(value.mul_div(dpi, 72 * FIXED_ONE)),
+            CssUnitPc(value) => Some(value.mul_div(dpi, 6 * FIXED_ONE)),
+            CssUnitDeg(_) | CssUnitGrad(_) | CssUnitRad(_) |
+            CssUnitMs(_) | CssUnitS(_) | CssUnitHz(_) | CssUnitKHz(_) => None
+        }
+    }
+}
+
+impl CssMarginValue {
+    pub fn to_px(&self, ctx: &LengthContext) -> Option<CssFixed> {
+        match *self {
+            CssMarginSet(ref unit) => unit.to_px(ctx),
+            CssMarginInherit | CssMarginAuto => None
+        }
+    }
+}
+
+impl CssPaddingValue {
+    pub fn to_px(&self, ctx: &LengthContext) -> Option<CssFixed> {
+        match *self {
+            CssPaddingSet(ref unit) => unit.to_px(ctx),
+            CssPaddingInherit => None
+        }
+    }
+}
+
+impl CssBorderWidthValue {
+    /// The keywords are 1, 2 and 4 pixels wide, as libcss computes them
+    pub fn to_px(&self, ctx: &LengthContext) -> Option<CssFixed> {
+        match *self {
+            CssBorderWidthThin => Some(CssFixed::from_int(1)),
+            CssBorderWidthMedium => Some(CssFixed::from_int(2)),
+            CssBorderWidthThick => Some(CssFixed::from_int(4)),
+            CssBorderWidthWidth(ref unit) => unit.to_px(ctx),
+            CssBorderWidthInherit => None
+        }
+    }
+}
+
+impl CssWidthValue {
+    pub fn to_px(&self, ctx: &LengthContext) -> Option<CssFixed> {
+        match *self {
+            CssWidthSet(ref unit) => unit.to_px(ctx),
+            CssWidthInherit | CssWidthAuto => None
+        }
+    }
+}
+
+impl CssHeightValue {
+    pub fn to_px(&self, ctx: &LengthContext) -> Option<CssFixed> {
+        match *self {
+            CssHeightSet(ref unit) => unit.to_px(ctx),
+            CssHeightInherit | CssHeightAuto => None
+        }
+    }
+}
+
+impl CssFontSizeValue {
+    /// `ctx` should describe the parent's font. Keywords are left to the
+    /// caller
+    pub fn to_px(&self, ctx: &LengthContext) -> Option<CssFixed> {
+        match *self {
+            CssFontSizeDimension(ref unit) => unit.to_px(ctx),
+            _ => None
+        }
+    }
+}
+
+impl CssLineHeightValue {
+    /// Numbers are multiples of the font size
+    pub fn to_px(&self, ctx: &LengthContext) -> Option<CssFixed> {
+        match *self {
+            CssLineHeightNumber(number) => Some(number.mul_div(ctx.font_size.to_raw() as i64, FIXED_ONE)),
+            CssLineHeightDimension(ref unit) => unit.to_px(ctx),
+            CssLineHeightInherit | CssLineHeightNormal => None
+        }
+    }
+}
+
+impl CssVerticalAlignValue {
+    /// Percentages are of the line height, so that should be the basis
+    pub fn to_px(&self, ctx: &LengthContext) -> Option<CssFixed> {
+        match *self {
+            CssVerticalAlignDimension(ref unit) => unit.to_px(ctx),
+            _ => None
+        }
+    }
+}
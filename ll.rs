@@ -479,12 +479,12 @@ pub mod stylesheet {
 
     pub type css_url_resolution_fn = extern "C" fn(pw: *c_void, base: *c_char, rel: *lwc_string, abs: *mut *lwc_string) -> css_error;
     pub type css_import_notification_fn = extern "C" fn(pw: *c_void, parent: *css_stylesheet, url: *lwc_string, media: *uint64_t) -> css_error;
-    pub type css_color_resolution_fn = extern "C" fn(pw: *c_void, name: *lwc_string, color: *css_color) -> css_error;
-    pub type css_font_resolution_fn = extern "C" fn(pw: *c_void, name: *lwc_string, system_font: *css_system_font) -> css_error;
+    pub type css_color_resolution_fn = extern "C" fn(pw: *c_void, name: *lwc_string, color: *mut css_color) -> css_error;
+    pub type css_font_resolution_fn = extern "C" fn(pw: *c_void, name: *lwc_string, system_font: *mut css_system_font) -> css_error;
 
     pub type css_stylesheet = c_void;
 
-    struct css_system_font {
+    pub struct css_system_font {
         style: css_font_style_e,
         variant: css_font_variant_e,
         weight: css_font_weight_e,
@@ -510,6 +510,8 @@ pub mod stylesheet {
         pub fn css_stylesheet_size(sheet: *css_stylesheet, size: *mut size_t) -> css_error;
         pub fn css_stylesheet_append_data(sheet: *css_stylesheet, data: *uint8_t, len: size_t) -> css_error;
         pub fn css_stylesheet_data_done(sheet: *css_stylesheet) -> css_error;
+        pub fn css_stylesheet_get_language_level(sheet: *css_stylesheet, level: *mut css_language_level) -> css_error;
+        pub fn css_stylesheet_used_quirks(sheet: *css_stylesheet, quirks: *mut bool) -> css_error;
     }
 }
 
@@ -552,7 +554,7 @@ pub mod select {
         named_ancestor_node: extern "C" fn(*c_void, *c_void, *css_qname, *mut *c_void) -> css_error,
         named_parent_node: extern "C" fn(*c_void, *c_void, *css_qname, *mut *c_void) -> css_error,
         named_sibling_node: extern "C" fn(*c_void, *c_void, *css_qname, *mut *c_void) -> css_error,
-        named_generic_sibling_node: extern "C" fn(*c_void, *c_void, *css_qname, **c_void) -> css_error,
+        named_generic_sibling_node: extern "C" fn(*c_void, *c_void, *css_qname, *mut *c_void) -> css_error,
         parent_node: extern "C" fn(*c_void, *c_void, *mut *c_void) -> css_error,
         sibling_node: extern "C" fn(*c_void, *c_void, *mut *c_void) -> css_error,
         node_has_name: extern "C" fn(*c_void, *c_void, *css_qname, *bool) -> css_error,
@@ -560,7 +562,7 @@ pub mod select {
         node_has_id: extern "C" fn(*c_void, *c_void, *lwc_string, *mut bool) -> css_error,
         node_has_attribute: extern "C" fn(*c_void, *c_void, *css_qname, *mut bool) -> css_error,
         node_has_attribute_equal: extern "C" fn(*c_void, *c_void, *css_qname, *lwc_string, *mut bool) -> css_error,
-        node_has_attribute_dashmatch: extern "C" fn(*c_void, *c_void, *css_qname, *lwc_string, *bool) -> css_error,
+        node_has_attribute_dashmatch: extern "C" fn(*c_void, *c_void, *css_qname, *lwc_string, *mut bool) -> css_error,
         node_has_attribute_includes: extern "C" fn(*c_void, *c_void, *css_qname, *lwc_string, *mut bool) -> css_error,
         node_has_attribute_prefix: extern "C" fn(*c_void, *c_void, *css_qname, *lwc_string, *mut bool) -> css_error,
         node_has_attribute_suffix: extern "C" fn(*c_void, *c_void, *css_qname, *lwc_string, *mut bool) -> css_error,
@@ -587,7 +589,10 @@ pub mod select {
         pub fn css_select_ctx_create(alloc: css_allocator_fn, pw: *c_void, result: *mut *css_select_ctx) -> css_error;
         pub fn css_select_ctx_destroy(ctx: *css_select_ctx) -> css_error;
         pub fn css_select_ctx_append_sheet(ctx: *css_select_ctx, sheet: *css_stylesheet, origin: css_origin, media: uint64_t) -> css_error;
+        pub fn css_select_ctx_insert_sheet(ctx: *css_select_ctx, sheet: *css_stylesheet, index: uint32_t, origin: css_origin, media: uint64_t) -> css_error;
+        pub fn css_select_ctx_remove_sheet(ctx: *css_select_ctx, sheet: *css_stylesheet) -> css_error;
         pub fn css_select_ctx_count_sheets(ctx: *css_select_ctx, count: *mut uint32_t) -> css_error;
+        pub fn css_select_ctx_get_sheet(ctx: *css_select_ctx, index: uint32_t, sheet: *mut *css_stylesheet) -> css_error;
         pub fn css_select_style(ctx: *css_select_ctx, node: *c_void, media: uint64_t, inline_style: *css_stylesheet, handler: *css_select_handler, pw: *c_void, result: *mut *css_select_results) -> css_error;
         pub fn css_select_results_destroy(results: *css_select_results) -> css_error;
     }
@@ -599,6 +604,7 @@ pub mod computed {
     use ll::hint::css_hint;
     use ll::types::css_color;
     use super::errors::css_error;
+    use super::functypes::css_allocator_fn;
     use super::properties::{CSS_BORDER_WIDTH_WIDTH, CSS_FONT_SIZE_DIMENSION, CSS_HEIGHT_SET};
     use super::properties::{CSS_MARGIN_SET, CSS_PADDING_SET, CSS_WIDTH_SET};
     use super::stylesheet::css_fixed;
@@ -885,6 +891,10 @@ pub mod computed {
     pub type compute_font_size_cb = extern "C" fn(pw: *c_void, parent: *css_hint, size: *mut css_hint) -> css_error;
 
     extern {
+        pub fn css_computed_style_create(alloc: css_allocator_fn,
+                                         pw: *c_void,
+                                         result: *mut *css_computed_style) -> css_error;
+        pub fn css_computed_style_destroy(style: *css_computed_style) -> css_error;
         pub fn css_computed_style_compose(parent: *css_computed_style,
                                           child: *css_computed_style,
                                           compute_font_size: compute_font_size_cb,
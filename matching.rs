@@ -0,0 +1,475 @@
+// Copyright 2013 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+/*!
+
+Selector matching outside of the cascade, for `querySelector`,
+`querySelectorAll` and `Element.matches`.
+
+Nodes are examined through the same `CssSelectHandler` and `CssTreeHandler`
+methods libcss is answered from in `CssSelectCtx::select_style_in_tree`, so a
+selector matches the same nodes in both. Attribute selectors use
+`node_attribute`, whose default finds no attributes at all.
+
+*/
+
+use std::ascii::StrAsciiExt;
+use tokenizer::*;
+use select::{CssSelectHandler, CssTreeHandler};
+use CssResult;
+use errors::CssInvalid;
+use wapcaplet::from_rust_string;
+
+// Shared with select, whose attribute callbacks for libcss answer the same way
+#[deriving(Eq, Clone)]
+pub enum AttributeOperator {
+    AttributeExists,
+    AttributeEquals,
+    AttributeIncludes,
+    AttributeDashMatch,
+    AttributePrefix,
+    AttributeSuffix,
+    AttributeSubstring
+}
+
+#[deriving(Eq, Clone)]
+enum PseudoClass {
+    PseudoClassRoot,
+    PseudoClassLink,
+    PseudoClassVisited,
+    PseudoClassFirstChild,
+    PseudoClassLastChild,
+    PseudoClassOnlyChild,
+    PseudoClassEmpty,
+    // Dynamic states the handler can not report, like :hover
+    PseudoClassNever
+}
+
+#[deriving(Eq, Clone)]
+enum SimpleSelector {
+    // Lowercased, since element names are matched ignoring case
+    SimpleSelectorType(~str),
+    SimpleSelectorId(~str),
+    SimpleSelectorClass(~str),
+    SimpleSelectorAttribute(~str, AttributeOperator, ~str),
+    SimpleSelectorPseudoClass(PseudoClass),
+    // The simple selectors of a compound selector, none of which may match
+    SimpleSelectorNot(~[SimpleSelector])
+}
+
+#[deriving(Eq, Clone)]
+enum Combinator {
+    CombinatorDescendant,
+    CombinatorChild,
+    CombinatorNextSibling,
+    CombinatorSubsequentSibling
+}
+
+#[deriving(Eq, Clone)]
+struct ComplexSelector {
+    // Rightmost first, with combinators[i] joining compounds[i] to the
+    // compound on its left, compounds[i + 1]
+    compounds: ~[~[SimpleSelector]],
+    combinators: ~[Combinator],
+    // Pseudo-elements are not nodes, so such a selector matches nothing
+    pseudo_element: bool
+}
+
+/// A parsed selector list, e.g. "ul > li.done, #main p"
+#[deriving(Eq, Clone)]
+pub struct CssSelectorList {
+    priv selectors: ~[ComplexSelector]
+}
+
+static LEGACY_PSEUDO_ELEMENTS: &'static [&'static str] = &["after", "before", "first-letter", "first-line"];
+
+impl CssSelectorList {
+    /// `CssInvalid` if `text` is not a selector list
+    pub fn parse(text: &str) -> CssResult<CssSelectorList> {
+        let mut parser = SelectorParser {
+            tokens: Tokenizer::new(text).filter(|&(ref token, _)| {
+                match *token { TokenComment(_) => false, _ => true }
+            }).map(|(token, _)| token).collect(),
+            pos: 0
+        };
+        parser.selector_list()
+    }
+
+    /// Whether `node` matches any selector in the list, like `Element.matches`
+    pub fn matches<N, H: CssSelectHandler<N> + CssTreeHandler<N>>(&self, node: &N,
+                                                                  handler: &H) -> bool {
+        self.selectors.iter().any(|selector| matches_complex(selector, node, handler))
+    }
+
+    /// The first descendant of `root` in document order that matches
+    pub fn query_selector<N, H: CssSelectHandler<N> + CssTreeHandler<N>>(&self, root: &N,
+                                                                         handler: &H) -> Option<N> {
+        let mut found = self.collect(root, handler, true);
+        if found.is_empty() { None } else { Some(found.pop()) }
+    }
+
+    /// Every descendant of `root` that matches, in document order
+    pub fn query_selector_all<N, H: CssSelectHandler<N> + CssTreeHandler<N>>(&self, root: &N,
+                                                                             handler: &H) -> ~[N] {
+        self.collect(root, handler, false)
+    }
+
+    // Walks the descendants of `root` in document order, a node at a time so
+    // that deep trees do not run out of stack, stopping at the first match
+    // if only that was wanted
+    fn collect<N, H: CssSelectHandler<N> + CssTreeHandler<N>>(&self, root: &N, handler: &H,
+                                                              first_only: bool) -> ~[N] {
+        let mut found = ~[];
+        // The nodes still to visit, the next in document order last
+        let mut pending = ~[];
+        push_children(root, handler, &mut pending);
+        while !pending.is_empty() {
+            let node = pending.pop();
+            push_children(&node, handler, &mut pending);
+            if self.matches(&node, handler) {
+                found.push(node);
+                if first_only {
+                    break;
+                }
+            }
+        }
+        found
+    }
+}
+
+fn push_children<N, H: CssTreeHandler<N>>(node: &N, handler: &H, pending: &mut ~[N]) {
+    let mut children = ~[];
+    let mut child = handler.first_child_node(node);
+    loop {
+        match child {
+            Some(node) => {
+                child = handler.next_sibling_node(&node);
+                children.push(node);
+            }
+            None => break
+        }
+    }
+    // Reversed, so that the first child is visited first
+    while !children.is_empty() {
+        pending.push(children.pop());
+    }
+}
+
+fn matches_complex<N, H: CssSelectHandler<N> + CssTreeHandler<N>>(selector: &ComplexSelector, node: &N,
+                                                                  handler: &H) -> bool {
+    !selector.pseudo_element && matches_from(selector, 0, node, handler)
+}
+
+// Whether `node` matches the compound at `index` and everything to its left
+fn matches_from<N, H: CssSelectHandler<N> + CssTreeHandler<N>>(selector: &ComplexSelector, index: uint,
+                                                               node: &N, handler: &H) -> bool {
+    if !selector.compounds[index].iter().all(|simple| matches_simple(simple, node, handler)) {
+        return false;
+    }
+    if index + 1 == selector.compounds.len() {
+        return true;
+    }
+    // Whether to look at siblings rather than ancestors, and whether at more than one
+    let (siblings, repeat) = match selector.combinators[index] {
+        CombinatorDescendant => (false, true),
+        CombinatorChild => (false, false),
+        CombinatorNextSibling => (true, false),
+        CombinatorSubsequentSibling => (true, true)
+    };
+    let next = |n: &N| if siblings { handler.prev_sibling_node(n) } else { handler.parent_node(n) };
+    let mut current = next(node);
+    loop {
+        match current {
+            Some(candidate) => {
+                if matches_from(selector, index + 1, &candidate, handler) {
+                    return true;
+                }
+                if !repeat {
+                    return false;
+                }
+                current = next(&candidate);
+            }
+            None => return false
+        }
+    }
+}
+
+fn matches_simple<N, H: CssSelectHandler<N> + CssTreeHandler<N>>(simple: &SimpleSelector, node: &N,
+                                                                 handler: &H) -> bool {
+    match *simple {
+        SimpleSelectorType(ref name) => {
+            handler.node_name(node).name.to_str_slice().eq_ignore_ascii_case(*name)
+        }
+        SimpleSelectorId(ref id) => handler.node_has_id(node, from_rust_string(*id)),
+        SimpleSelectorClass(ref class) => handler.node_has_class(node, from_rust_string(*class)),
+        SimpleSelectorAttribute(ref name, operator, ref value) => {
+            match handler.node_attribute(node, *name) {
+                Some(actual) => matches_attribute(actual, operator, *value),
+                None => false
+            }
+        }
+        SimpleSelectorPseudoClass(pseudo_class) => {
+            match pseudo_class {
+                PseudoClassRoot => handler.node_is_root(node),
+                PseudoClassLink => handler.node_is_link(node),
+                PseudoClassVisited => handler.node_is_visited(node),
+                PseudoClassFirstChild => handler.prev_sibling_node(node).is_none(),
+                PseudoClassLastChild => handler.next_sibling_node(node).is_none(),
+                PseudoClassOnlyChild => {
+                    handler.prev_sibling_node(node).is_none() && handler.next_sibling_node(node).is_none()
+                }
+                PseudoClassEmpty => handler.first_child_node(node).is_none(),
+                PseudoClassNever => false
+            }
+        }
+        SimpleSelectorNot(ref compound) => {
+            !compound.iter().all(|simple| matches_simple(simple, node, handler))
+        }
+    }
+}
+
+pub fn matches_attribute(actual: &str, operator: AttributeOperator, value: &str) -> bool {
+    match operator {
+        AttributeExists => true,
+        AttributeEquals => actual == value,
+        AttributeIncludes => actual.word_iter().any(|word| word == value),
+        AttributeDashMatch => actual == value || actual.starts_with(fmt!("%s-", value)),
+        // An empty value matches nothing for these three
+        AttributePrefix => !value.is_empty() && actual.starts_with(value),
+        AttributeSuffix => !value.is_empty() && actual.ends_with(value),
+        AttributeSubstring => !value.is_empty() && actual.contains(value)
+    }
+}
+
+struct SelectorParser {
+    tokens: ~[Token],
+    pos: uint
+}
+
+impl SelectorParser {
+    fn peek<'a>(&'a self) -> Option<&'a Token> {
+        if self.pos < self.tokens.len() { Some(&self.tokens[self.pos]) } else { None }
+    }
+
+    // A copy of the next token, so the parser can be mutated while matching on it
+    fn current(&self) -> Option<Token> {
+        match self.peek() {
+            Some(token) => Some(token.clone()),
+            None => None
+        }
+    }
+
+    fn skip_whitespace(&mut self) -> bool {
+        let start = self.pos;
+        while self.peek() == Some(&TokenWhitespace) {
+            self.pos += 1;
+        }
+        self.pos > start
+    }
+
+    fn selector_list(&mut self) -> CssResult<CssSelectorList> {
+        let mut selectors = ~[];
+        loop {
+            self.skip_whitespace();
+            match self.complex_selector() {
+                Ok(selector) => selectors.push(selector),
+                Err(e) => return Err(e)
+            }
+            match self.current() {
+                None => return Ok(CssSelectorList { selectors: selectors }),
+                Some(TokenComma) => self.pos += 1,
+                Some(_) => return Err(CssInvalid)
+            }
+        }
+    }
+
+    // Stops before a ',' or the end of the list
+    fn complex_selector(&mut self) -> CssResult<ComplexSelector> {
+        let mut compounds = ~[];
+        let mut combinators = ~[];
+        let mut pseudo_element = false;
+        loop {
+            // Only the last compound may have a pseudo-element
+            if pseudo_element {
+                return Err(CssInvalid);
+            }
+            match self.compound_selector(&mut pseudo_element) {
+                Ok(compound) => compounds.push(compound),
+                Err(e) => return Err(e)
+            }
+            let had_whitespace = self.skip_whitespace();
+            let combinator = match self.current() {
+                None | Some(TokenComma) => break,
+                Some(TokenDelim('>')) => CombinatorChild,
+                Some(TokenDelim('+')) => CombinatorNextSibling,
+                Some(TokenDelim('~')) => CombinatorSubsequentSibling,
+                Some(_) if had_whitespace => {
+                    combinators.push(CombinatorDescendant);
+                    continue;
+                }
+                Some(_) => return Err(CssInvalid)
+            };
+            self.pos += 1;
+            self.skip_whitespace();
+            combinators.push(combinator);
+        }
+        compounds.reverse();
+        combinators.reverse();
+        Ok(ComplexSelector {
+            compounds: compounds,
+            combinators: combinators,
+            pseudo_element: pseudo_element
+        })
+    }
+
+    fn compound_selector(&mut self, pseudo_element: &mut bool) -> CssResult<~[SimpleSelector]> {
+        let start = self.pos;
+        let mut compound = ~[];
+        match self.current() {
+            Some(TokenIdent(name)) => {
+                compound.push(SimpleSelectorType(name.to_ascii_lower()));
+                self.pos += 1;
+            }
+            Some(TokenDelim('*')) => self.pos += 1,
+            _ => ()
+        }
+        loop {
+            let simple = match self.current() {
+                Some(TokenHash(name)) => {
+                    self.pos += 1;
+                    SimpleSelectorId(name)
+                }
+                Some(TokenDelim('.')) => {
+                    self.pos += 1;
+                    match self.current() {
+                        Some(TokenIdent(name)) => {
+                            self.pos += 1;
+                            SimpleSelectorClass(name)
+                        }
+                        _ => return Err(CssInvalid)
+                    }
+                }
+                Some(TokenOpenBracket) => {
+                    match self.attribute_selector() {
+                        Ok(simple) => simple,
+                        Err(e) => return Err(e)
+                    }
+                }
+                Some(TokenColon) if !*pseudo_element => {
+                    self.pos += 1;
+                    match self.pseudo(pseudo_element) {
+                        Ok(Some(simple)) => simple,
+                        Ok(None) => continue,
+                        Err(e) => return Err(e)
+                    }
+                }
+                _ => break
+            };
+            compound.push(simple);
+        }
+        if self.pos == start {
+            Err(CssInvalid)
+        } else {
+            Ok(compound)
+        }
+    }
+
+    fn attribute_selector(&mut self) -> CssResult<SimpleSelector> {
+        self.pos += 1;
+        self.skip_whitespace();
+        let name = match self.current() {
+            Some(TokenIdent(name)) => name,
+            _ => return Err(CssInvalid)
+        };
+        self.pos += 1;
+        self.skip_whitespace();
+        let operator = match self.current() {
+            Some(TokenCloseBracket) => {
+                self.pos += 1;
+                return Ok(SimpleSelectorAttribute(name, AttributeExists, ~""));
+            }
+            Some(TokenDelim('=')) => AttributeEquals,
+            Some(TokenIncludes) => AttributeIncludes,
+            Some(TokenDashMatch) => AttributeDashMatch,
+            Some(TokenPrefixMatch) => AttributePrefix,
+            Some(TokenSuffixMatch) => AttributeSuffix,
+            Some(TokenSubstringMatch) => AttributeSubstring,
+            _ => return Err(CssInvalid)
+        };
+        self.pos += 1;
+        self.skip_whitespace();
+        let value = match self.current() {
+            Some(TokenIdent(value)) | Some(TokenString(value)) => value,
+            _ => return Err(CssInvalid)
+        };
+        self.pos += 1;
+        self.skip_whitespace();
+        match self.current() {
+            Some(TokenCloseBracket) => {
+                self.pos += 1;
+                Ok(SimpleSelectorAttribute(name, operator, value))
+            }
+            _ => Err(CssInvalid)
+        }
+    }
+
+    // After the ':'. None for a pseudo-element, which is only recorded
+    fn pseudo(&mut self, pseudo_element: &mut bool) -> CssResult<Option<SimpleSelector>> {
+        match self.current() {
+            Some(TokenColon) => {
+                self.pos += 1;
+                match self.current() {
+                    Some(TokenIdent(_)) => {
+                        self.pos += 1;
+                        *pseudo_element = true;
+                        Ok(None)
+                    }
+                    _ => Err(CssInvalid)
+                }
+            }
+            Some(TokenIdent(name)) => {
+                self.pos += 1;
+                let name = name.to_ascii_lower();
+                if LEGACY_PSEUDO_ELEMENTS.iter().any(|p| *p == name.as_slice()) {
+                    *pseudo_element = true;
+                    return Ok(None);
+                }
+                let pseudo_class = match name.as_slice() {
+                    "root" => PseudoClassRoot,
+                    "link" => PseudoClassLink,
+                    "visited" => PseudoClassVisited,
+                    "first-child" => PseudoClassFirstChild,
+                    "last-child" => PseudoClassLastChild,
+                    "only-child" => PseudoClassOnlyChild,
+                    "empty" => PseudoClassEmpty,
+                    "hover" | "active" | "focus" | "target" => PseudoClassNever,
+                    _ => return Err(CssInvalid)
+                };
+                Ok(Some(SimpleSelectorPseudoClass(pseudo_class)))
+            }
+            Some(TokenFunction(name)) if name.eq_ignore_ascii_case("not") => {
+                self.pos += 1;
+                self.skip_whitespace();
+                // Selectors Level 3 only allows a simple selector here
+                let mut inner_pseudo_element = false;
+                let compound = match self.compound_selector(&mut inner_pseudo_element) {
+                    Ok(compound) => compound,
+                    Err(e) => return Err(e)
+                };
+                self.skip_whitespace();
+                if inner_pseudo_element || self.current() != Some(TokenCloseParen) {
+                    return Err(CssInvalid);
+                }
+                self.pos += 1;
+                Ok(Some(SimpleSelectorNot(compound)))
+            }
+            _ => Err(CssInvalid)
+        }
+    }
+}
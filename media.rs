@@ -0,0 +1,642 @@
+// Copyright 2013 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+/*!
+
+Media types and media queries.
+
+libcss only understands media *types*, passed around as a bitmask. `CssMedia`
+is the typed version of that bitmask. Media *features* (Media Queries Level 3)
+are evaluated here, against a `MediaEnvironment`, and the result is turned back
+into a `CssMedia` that libcss can use.
+
+*/
+
+use std::ascii::StrAsciiExt;
+use std::from_str::from_str;
+use std::libc::types::common::c99::uint64_t;
+use ll::types::*;
+use conversions::ToLl;
+
+#[deriving(Eq, Clone)]
+pub enum CssMediaType {
+    CssMediaAural,
+    CssMediaBraille,
+    CssMediaEmbossed,
+    CssMediaHandheld,
+    CssMediaPrint,
+    CssMediaProjection,
+    CssMediaScreen,
+    CssMediaSpeech,
+    CssMediaTty,
+    CssMediaTv
+}
+
+static ALL_MEDIA_TYPES: &'static [CssMediaType] = &[
+    CssMediaAural, CssMediaBraille, CssMediaEmbossed, CssMediaHandheld, CssMediaPrint,
+    CssMediaProjection, CssMediaScreen, CssMediaSpeech, CssMediaTty, CssMediaTv
+];
+
+impl CssMediaType {
+    /// Looks up a media type by its (case-insensitive) CSS name
+    pub fn from_name(name: &str) -> Option<CssMediaType> {
+        let name = name.to_ascii_lower();
+        for media_type in ALL_MEDIA_TYPES.iter() {
+            if media_type.name() == name.as_slice() {
+                return Some(*media_type);
+            }
+        }
+        None
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            CssMediaAural => "aural",
+            CssMediaBraille => "braille",
+            CssMediaEmbossed => "embossed",
+            CssMediaHandheld => "handheld",
+            CssMediaPrint => "print",
+            CssMediaProjection => "projection",
+            CssMediaScreen => "screen",
+            CssMediaSpeech => "speech",
+            CssMediaTty => "tty",
+            CssMediaTv => "tv"
+        }
+    }
+
+    fn bit(&self) -> uint64_t {
+        match *self {
+            CssMediaAural => CSS_MEDIA_AURAL,
+            CssMediaBraille => CSS_MEDIA_BRAILLE,
+            CssMediaEmbossed => CSS_MEDIA_EMBOSSED,
+            CssMediaHandheld => CSS_MEDIA_HANDHELD,
+            CssMediaPrint => CSS_MEDIA_PRINT,
+            CssMediaProjection => CSS_MEDIA_PROJECTION,
+            CssMediaScreen => CSS_MEDIA_SCREEN,
+            CssMediaSpeech => CSS_MEDIA_SPEECH,
+            CssMediaTty => CSS_MEDIA_TTY,
+            CssMediaTv => CSS_MEDIA_TV
+        }
+    }
+}
+
+/// A set of media types. This is what libcss is given when appending a sheet
+/// to a selection context and when selecting styles.
+#[deriving(Eq, Clone)]
+pub struct CssMedia {
+    priv bits: uint64_t
+}
+
+impl CssMedia {
+    pub fn empty() -> CssMedia {
+        CssMedia { bits: 0 }
+    }
+
+    pub fn all() -> CssMedia {
+        CssMedia { bits: CSS_MEDIA_ALL }
+    }
+
+    pub fn single(media_type: CssMediaType) -> CssMedia {
+        CssMedia { bits: media_type.bit() }
+    }
+
+    /// Parses a comma-separated list of media types, e.g. `"screen, print"`.
+    /// Unknown media types are ignored, as CSS 2.1 requires. Returns `None`
+    /// if an entry is not a single identifier.
+    pub fn parse(list: &str) -> Option<CssMedia> {
+        let mut media = CssMedia::empty();
+        if list.trim().is_empty() {
+            return Some(CssMedia::all());
+        }
+        for entry in list.split_iter(',') {
+            let entry = entry.trim();
+            if entry.is_empty() || entry.contains_char(' ') || entry.contains_char('\t') {
+                return None;
+            }
+            if entry.eq_ignore_ascii_case("all") {
+                media = CssMedia::all();
+            } else {
+                match CssMediaType::from_name(entry) {
+                    Some(media_type) => media.insert(media_type),
+                    None => ()
+                }
+            }
+        }
+        Some(media)
+    }
+
+    pub fn insert(&mut self, media_type: CssMediaType) {
+        self.bits |= media_type.bit();
+    }
+
+    pub fn contains(&self, media_type: CssMediaType) -> bool {
+        (self.bits & media_type.bit()) != 0
+    }
+
+    pub fn intersects(&self, other: &CssMedia) -> bool {
+        (self.bits & other.bits) != 0
+    }
+
+    pub fn union(&self, other: &CssMedia) -> CssMedia {
+        CssMedia { bits: self.bits | other.bits }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bits == 0
+    }
+
+    pub fn types(&self) -> ~[CssMediaType] {
+        ALL_MEDIA_TYPES.iter().filter(|t| self.contains(**t)).map(|t| *t).collect()
+    }
+
+    /// The set as a media type list that `parse` gives back, e.g. `"screen,
+    /// print"`. Empty for the empty set, which no such list can express
+    pub fn to_media_list(&self) -> ~str {
+        if *self == CssMedia::all() {
+            ~"all"
+        } else {
+            let names: ~[&'static str] = self.types().iter().map(|t| t.name()).collect();
+            names.connect(", ")
+        }
+    }
+
+    /// The set libcss describes with `bits`
+    pub fn from_ll(bits: uint64_t) -> CssMedia {
+        CssMedia { bits: bits & CSS_MEDIA_ALL }
+    }
+}
+
+impl ToLl<uint64_t> for CssMedia {
+    #[inline]
+    fn to_ll(&self) -> uint64_t {
+        self.bits
+    }
+}
+
+/// A description of the device styles are being selected for. Lengths are in
+/// CSS pixels and resolution in dots per inch.
+pub struct MediaEnvironment {
+    media_type: CssMediaType,
+    width: f64,
+    height: f64,
+    resolution: f64,
+    // Bits per colour component. Zero for monochrome devices
+    color: uint,
+}
+
+impl MediaEnvironment {
+    pub fn media(&self) -> CssMedia {
+        CssMedia::single(self.media_type)
+    }
+}
+
+#[deriving(Eq, Clone)]
+pub enum MediaQualifier {
+    MediaQualifierNone,
+    MediaQualifierOnly,
+    MediaQualifierNot
+}
+
+#[deriving(Eq, Clone)]
+pub enum MediaQueryType {
+    MediaQueryAll,
+    MediaQueryOf(CssMediaType),
+    // A syntactically valid but unrecognised media type. Never matches
+    MediaQueryUnknown
+}
+
+#[deriving(Eq, Clone)]
+pub enum MediaFeature {
+    MediaFeatureWidth,
+    MediaFeatureHeight,
+    MediaFeatureOrientation,
+    MediaFeatureResolution,
+    MediaFeatureColor,
+    MediaFeatureAspectRatio
+}
+
+#[deriving(Eq, Clone)]
+pub enum MediaRange {
+    MediaRangeExact,
+    MediaRangeMin,
+    MediaRangeMax
+}
+
+#[deriving(Eq, Clone)]
+pub enum MediaOrientation {
+    MediaPortrait,
+    MediaLandscape
+}
+
+#[deriving(Eq, Clone)]
+pub enum MediaValue {
+    // In CSS pixels
+    MediaLength(f64),
+    // In dots per inch
+    MediaResolution(f64),
+    MediaInteger(uint),
+    MediaRatio(uint, uint),
+    MediaOrientationValue(MediaOrientation)
+}
+
+#[deriving(Eq, Clone)]
+pub struct MediaExpression {
+    feature: MediaFeature,
+    range: MediaRange,
+    value: Option<MediaValue>
+}
+
+#[deriving(Eq, Clone)]
+pub struct MediaQuery {
+    qualifier: MediaQualifier,
+    media_type: MediaQueryType,
+    expressions: ~[MediaExpression]
+}
+
+#[deriving(Eq, Clone)]
+pub struct MediaQueryList {
+    queries: ~[MediaQuery]
+}
+
+// Length units in media queries are resolved against the initial font
+static INITIAL_FONT_SIZE: f64 = 16.0;
+
+impl MediaQuery {
+    /// The query that malformed input is replaced with, per Media Queries 3.3.1
+    pub fn not_all() -> MediaQuery {
+        MediaQuery {
+            qualifier: MediaQualifierNot,
+            media_type: MediaQueryAll,
+            expressions: ~[]
+        }
+    }
+
+    /// Parses a single media query. Malformed queries become `not all`
+    pub fn parse(text: &str) -> MediaQuery {
+        match parse_query(text.to_ascii_lower()) {
+            Some(query) => query,
+            None => MediaQuery::not_all()
+        }
+    }
+
+    pub fn evaluate(&self, env: &MediaEnvironment) -> bool {
+        let type_matches = match self.media_type {
+            MediaQueryAll => true,
+            MediaQueryOf(media_type) => media_type == env.media_type,
+            MediaQueryUnknown => false
+        };
+        let matches = type_matches && self.expressions.iter().all(|e| e.evaluate(env));
+        match self.qualifier {
+            MediaQualifierNot => !matches,
+            _ => matches
+        }
+    }
+}
+
+impl MediaQueryList {
+    /// Parses a comma-separated media query list. An empty list matches all media
+    pub fn parse(text: &str) -> MediaQueryList {
+        let mut queries = ~[];
+        if !text.trim().is_empty() {
+            for query in split_top_level_commas(text).iter() {
+                queries.push(MediaQuery::parse(*query));
+            }
+        }
+        MediaQueryList { queries: queries }
+    }
+
+    pub fn evaluate(&self, env: &MediaEnvironment) -> bool {
+        self.queries.is_empty() || self.queries.iter().any(|q| q.evaluate(env))
+    }
+
+    /// The media types for which this list matches, given the features of
+    /// `env`. This is the value to hand to libcss for a sheet guarded by
+    /// the list, whether it comes from a `<link media>` attribute, an
+    /// `@import` or an `@media` block.
+    pub fn to_media(&self, env: &MediaEnvironment) -> CssMedia {
+        let mut media = CssMedia::empty();
+        for media_type in ALL_MEDIA_TYPES.iter() {
+            let typed_env = MediaEnvironment {
+                media_type: *media_type,
+                .. *env
+            };
+            if self.evaluate(&typed_env) {
+                media.insert(*media_type);
+            }
+        }
+        media
+    }
+}
+
+impl MediaExpression {
+    pub fn evaluate(&self, env: &MediaEnvironment) -> bool {
+        match (self.feature, self.value) {
+            (MediaFeatureWidth, None) => env.width != 0.0,
+            (MediaFeatureWidth, Some(MediaLength(px))) => compare(self.range, env.width, px),
+            (MediaFeatureHeight, None) => env.height != 0.0,
+            (MediaFeatureHeight, Some(MediaLength(px))) => compare(self.range, env.height, px),
+            (MediaFeatureOrientation, None) => true,
+            (MediaFeatureOrientation, Some(MediaOrientationValue(orientation))) => {
+                orientation == if env.height >= env.width { MediaPortrait } else { MediaLandscape }
+            }
+            (MediaFeatureResolution, None) => env.resolution != 0.0,
+            (MediaFeatureResolution, Some(MediaResolution(dpi))) => compare(self.range, env.resolution, dpi),
+            (MediaFeatureColor, None) => env.color != 0,
+            (MediaFeatureColor, Some(MediaInteger(bits))) => {
+                compare(self.range, env.color as f64, bits as f64)
+            }
+            (MediaFeatureAspectRatio, None) => env.width != 0.0 && env.height != 0.0,
+            (MediaFeatureAspectRatio, Some(MediaRatio(w, h))) => {
+                // Cross-multiply to avoid dividing by a zero height
+                compare(self.range, env.width * (h as f64), env.height * (w as f64))
+            }
+            _ => false
+        }
+    }
+}
+
+fn compare(range: MediaRange, actual: f64, wanted: f64) -> bool {
+    match range {
+        MediaRangeExact => actual == wanted,
+        MediaRangeMin => actual >= wanted,
+        MediaRangeMax => actual <= wanted
+    }
+}
+
+fn split_top_level_commas<'a>(text: &'a str) -> ~[&'a str] {
+    let mut parts = ~[];
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, b) in text.byte_iter().enumerate() {
+        match b as char {
+            '(' => depth += 1,
+            ')' => if depth > 0 { depth -= 1 },
+            ',' if depth == 0 => {
+                parts.push(text.slice(start, i));
+                start = i + 1;
+            }
+            _ => ()
+        }
+    }
+    parts.push(text.slice_from(start));
+    parts
+}
+
+enum QueryToken {
+    QueryIdent(~str),
+    QueryParen(~str)
+}
+
+fn tokenize_query(text: &str) -> Option<~[QueryToken]> {
+    let mut tokens = ~[];
+    let mut i = 0;
+    let len = text.len();
+    while i < len {
+        // Only ASCII bytes are looked at, so slicing never splits a character
+        let c = text[i];
+        if is_query_whitespace(c) {
+            i += 1;
+        } else if c == '(' as u8 {
+            match text.slice_from(i).find(')') {
+                Some(end) => {
+                    tokens.push(QueryParen(text.slice(i + 1, i + end).trim().to_owned()));
+                    i += end + 1;
+                }
+                None => return None
+            }
+        } else if c == ')' as u8 {
+            return None;
+        } else {
+            let start = i;
+            while i < len && !is_query_whitespace(text[i]) && text[i] != '(' as u8 {
+                i += 1;
+            }
+            tokens.push(QueryIdent(text.slice(start, i).to_owned()));
+        }
+    }
+    Some(tokens)
+}
+
+fn is_query_whitespace(b: u8) -> bool {
+    b == ' ' as u8 || b == '\t' as u8 || b == '\n' as u8 || b == '\r' as u8 || b == '\x0c' as u8
+}
+
+fn token_at<'a>(tokens: &'a [QueryToken], i: uint) -> Option<&'a QueryToken> {
+    if i < tokens.len() { Some(&tokens[i]) } else { None }
+}
+
+// Expects lowercased input
+fn parse_query(text: &str) -> Option<MediaQuery> {
+    let tokens = match tokenize_query(text) {
+        Some(tokens) => tokens,
+        None => return None
+    };
+
+    let mut qualifier = MediaQualifierNone;
+    let mut media_type = MediaQueryAll;
+    let mut expressions = ~[];
+    let mut i = 0;
+
+    match token_at(tokens, 0) {
+        Some(&QueryIdent(ref ident)) => {
+            match ident.as_slice() {
+                "only" => { qualifier = MediaQualifierOnly; i += 1; }
+                "not" => { qualifier = MediaQualifierNot; i += 1; }
+                _ => ()
+            }
+            match token_at(tokens, i) {
+                Some(&QueryIdent(ref name)) if name.as_slice() != "and" => {
+                    media_type = if name.as_slice() == "all" {
+                        MediaQueryAll
+                    } else {
+                        match CssMediaType::from_name(*name) {
+                            Some(t) => MediaQueryOf(t),
+                            None => MediaQueryUnknown
+                        }
+                    };
+                    i += 1;
+                }
+                _ => return None
+            }
+            // After a media type, every expression is introduced by 'and'
+            while i < tokens.len() {
+                match (&tokens[i], token_at(tokens, i + 1)) {
+                    (&QueryIdent(ref and), Some(&QueryParen(ref expr))) if and.as_slice() == "and" => {
+                        match parse_expression(*expr) {
+                            Some(e) => expressions.push(e),
+                            None => return None
+                        }
+                        i += 2;
+                    }
+                    _ => return None
+                }
+            }
+        }
+        Some(&QueryParen(_)) => {
+            // A query may start with an expression, in which case the type is 'all'
+            let mut expect_and = false;
+            for token in tokens.iter() {
+                match (expect_and, token) {
+                    (false, &QueryParen(ref expr)) => {
+                        match parse_expression(*expr) {
+                            Some(e) => expressions.push(e),
+                            None => return None
+                        }
+                    }
+                    (true, &QueryIdent(ref and)) if and.as_slice() == "and" => (),
+                    _ => return None
+                }
+                expect_and = !expect_and;
+            }
+            if !expect_and {
+                return None;
+            }
+        }
+        None => return None
+    }
+
+    Some(MediaQuery {
+        qualifier: qualifier,
+        media_type: media_type,
+        expressions: expressions
+    })
+}
+
+fn parse_expression(text: &str) -> Option<MediaExpression> {
+    let (name, value) = match text.find(':') {
+        Some(colon) => (text.slice_to(colon).trim(), Some(text.slice_from(colon + 1).trim())),
+        None => (text.trim(), None)
+    };
+
+    let (range, name) = if name.starts_with("min-") {
+        (MediaRangeMin, name.slice_from(4))
+    } else if name.starts_with("max-") {
+        (MediaRangeMax, name.slice_from(4))
+    } else {
+        (MediaRangeExact, name)
+    };
+
+    let feature = match name {
+        "width" => MediaFeatureWidth,
+        "height" => MediaFeatureHeight,
+        "orientation" => MediaFeatureOrientation,
+        "resolution" => MediaFeatureResolution,
+        "color" => MediaFeatureColor,
+        "aspect-ratio" => MediaFeatureAspectRatio,
+        _ => return None
+    };
+
+    let value = match value {
+        // min- and max- prefixed features always take a value
+        None if range != MediaRangeExact => return None,
+        None => None,
+        Some(value) => {
+            let parsed = match feature {
+                MediaFeatureWidth | MediaFeatureHeight => parse_length(value),
+                MediaFeatureResolution => parse_resolution(value),
+                MediaFeatureColor => parse_integer(value),
+                MediaFeatureAspectRatio => parse_ratio(value),
+                MediaFeatureOrientation if range == MediaRangeExact => parse_orientation(value),
+                MediaFeatureOrientation => None
+            };
+            match parsed {
+                Some(v) => Some(v),
+                None => return None
+            }
+        }
+    };
+
+    Some(MediaExpression {
+        feature: feature,
+        range: range,
+        value: value
+    })
+}
+
+fn split_number<'a>(text: &'a str) -> Option<(f64, &'a str)> {
+    let mut end = 0;
+    for (i, b) in text.byte_iter().enumerate() {
+        let c = b as char;
+        if c.is_digit() || c == '.' || (i == 0 && c == '-') {
+            end = i + 1;
+        } else {
+            break;
+        }
+    }
+    match from_str::<f64>(text.slice_to(end)) {
+        Some(number) => Some((number, text.slice_from(end))),
+        None => None
+    }
+}
+
+fn parse_length(text: &str) -> Option<MediaValue> {
+    let (number, unit) = match split_number(text) {
+        Some(parts) => parts,
+        None => return None
+    };
+    if number < 0.0 {
+        return None;
+    }
+    let px = match unit {
+        "px" => number,
+        "em" => number * INITIAL_FONT_SIZE,
+        "ex" => number * INITIAL_FONT_SIZE / 2.0,
+        "in" => number * 96.0,
+        "cm" => number * 96.0 / 2.54,
+        "mm" => number * 96.0 / 25.4,
+        "pt" => number * 96.0 / 72.0,
+        "pc" => number * 96.0 / 6.0,
+        // Only zero may be unitless
+        "" if number == 0.0 => 0.0,
+        _ => return None
+    };
+    Some(MediaLength(px))
+}
+
+fn parse_resolution(text: &str) -> Option<MediaValue> {
+    let (number, unit) = match split_number(text) {
+        Some(parts) => parts,
+        None => return None
+    };
+    if number <= 0.0 {
+        return None;
+    }
+    match unit {
+        "dpi" => Some(MediaResolution(number)),
+        "dpcm" => Some(MediaResolution(number * 2.54)),
+        "dppx" => Some(MediaResolution(number * 96.0)),
+        _ => None
+    }
+}
+
+fn parse_integer(text: &str) -> Option<MediaValue> {
+    match from_str::<uint>(text) {
+        Some(n) => Some(MediaInteger(n)),
+        None => None
+    }
+}
+
+fn parse_ratio(text: &str) -> Option<MediaValue> {
+    let parts: ~[&str] = text.split_iter('/').map(|s| s.trim()).collect();
+    if parts.len() != 2 {
+        return None;
+    }
+    match (from_str::<uint>(parts[0]), from_str::<uint>(parts[1])) {
+        (Some(w), Some(h)) if w > 0 && h > 0 => Some(MediaRatio(w, h)),
+        _ => None
+    }
+}
+
+fn parse_orientation(text: &str) -> Option<MediaValue> {
+    match text {
+        "portrait" => Some(MediaOrientationValue(MediaPortrait)),
+        "landscape" => Some(MediaOrientationValue(MediaLandscape)),
+        _ => None
+    }
+}
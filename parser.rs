@@ -0,0 +1,1244 @@
+// Copyright 2013 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+/*!
+
+Parses the source of a sheet into a rule tree, following the error recovery
+rules of CSS 2.1 section 4.2, and records a diagnostic for everything that
+gets dropped.
+
+The syntax and property names are checked here. Whether a value is one libcss
+keeps, where `color: 12px` or a unitless `margin: 10` in strict mode is not,
+is left to a check the caller passes in; the stylesheet asks libcss itself.
+
+*/
+
+use std::ascii::StrAsciiExt;
+use std::util;
+use tokenizer::*;
+use diagnostics::*;
+use rules::*;
+
+static KNOWN_PROPERTIES: &'static [&'static str] = &[
+    "azimuth", "background", "background-attachment", "background-color", "background-image",
+    "background-position", "background-repeat", "border", "border-bottom", "border-bottom-color",
+    "border-bottom-style", "border-bottom-width", "border-collapse", "border-color", "border-left",
+    "border-left-color", "border-left-style", "border-left-width", "border-right",
+    "border-right-color", "border-right-style", "border-right-width", "border-spacing",
+    "border-style", "border-top", "border-top-color", "border-top-style", "border-top-width",
+    "border-width", "bottom", "break-after", "break-before", "break-inside", "caption-side",
+    "clear", "clip", "color", "column-count", "column-fill", "column-gap", "column-rule",
+    "column-rule-color", "column-rule-style", "column-rule-width", "column-span", "column-width",
+    "columns", "content", "counter-increment", "counter-reset", "cue", "cue-after", "cue-before",
+    "cursor", "direction", "display", "elevation", "empty-cells", "float", "font", "font-family",
+    "font-size", "font-style", "font-variant", "font-weight", "height", "left", "letter-spacing",
+    "line-height", "list-style", "list-style-image", "list-style-position", "list-style-type",
+    "margin", "margin-bottom", "margin-left", "margin-right", "margin-top", "max-height",
+    "max-width", "min-height", "min-width", "opacity", "orphans", "outline", "outline-color",
+    "outline-style", "outline-width", "overflow", "padding", "padding-bottom", "padding-left",
+    "padding-right", "padding-top", "page-break-after", "page-break-before", "page-break-inside",
+    "pause", "pause-after", "pause-before", "pitch", "pitch-range", "play-during", "position",
+    "quotes", "richness", "right", "speak", "speak-header", "speak-numeral", "speak-punctuation",
+    "speech-rate", "stress", "table-layout", "text-align", "text-decoration", "text-indent",
+    "text-transform", "top", "unicode-bidi", "vertical-align", "visibility", "voice-family",
+    "volume", "white-space", "widows", "width", "word-spacing", "z-index"
+];
+
+static FONT_FACE_DESCRIPTORS: &'static [&'static str] = &[
+    "font-family", "font-stretch", "font-style", "font-variant", "font-weight", "src",
+    "unicode-range"
+];
+
+/// The kind of block a declaration is in, which decides what it may set
+#[deriving(Eq, Clone)]
+pub enum CssDeclarationContext {
+    // A style rule or a style attribute
+    CssDeclarationsInStyle,
+    CssDeclarationsInPage,
+    CssDeclarationsInFontFace
+}
+
+/// Whether libcss knows the property `name`, which must be lowercase
+pub fn is_known_property(name: &str) -> bool {
+    KNOWN_PROPERTIES.iter().any(|p| *p == name)
+}
+
+/// The rules of a whole sheet and the diagnostics for what was dropped
+pub fn parse_stylesheet(text: &str) -> (~[CssRule], ~[CssDiagnostic]) {
+    parse_stylesheet_checked(text, |_, _| true)
+}
+
+/// As `parse_stylesheet`, also dropping the declarations `check` rejects
+pub fn parse_stylesheet_checked(text: &str, check: &fn(CssDeclarationContext, &CssDeclaration) -> bool)
+                                -> (~[CssRule], ~[CssDiagnostic]) {
+    let mut parser = Parser::new(text, check);
+    let rules = parser.stylesheet(true);
+    (rules, util::replace(&mut parser.diagnostics, ~[]))
+}
+
+/// The rules in `text`, as if it came after `@namespace` rules declaring
+/// `namespaces`, dropping the declarations `check` rejects. For a rule
+/// about to be inserted into a sheet
+pub fn parse_rules(text: &str, namespaces: &[~str],
+                   check: &fn(CssDeclarationContext, &CssDeclaration) -> bool)
+                   -> (~[CssRule], ~[CssDiagnostic]) {
+    let mut parser = Parser::new(text, check);
+    parser.namespaces = namespaces.to_owned();
+    let rules = parser.stylesheet(true);
+    (rules, util::replace(&mut parser.diagnostics, ~[]))
+}
+
+/// The declarations in a list without braces, like a style attribute
+pub fn parse_declarations(text: &str) -> (~[CssDeclaration], ~[CssDiagnostic]) {
+    parse_declarations_in(text, CssDeclarationsInStyle, |_, _| true)
+}
+
+/// The declarations in a list without braces, as if in a block of the kind
+/// `context` gives, dropping those `check` rejects
+pub fn parse_declarations_in(text: &str, context: CssDeclarationContext,
+                             check: &fn(CssDeclarationContext, &CssDeclaration) -> bool)
+                             -> (~[CssDeclaration], ~[CssDiagnostic]) {
+    let mut parser = Parser::new(text, check);
+    let declarations = parser.declarations(context, false);
+    (declarations, util::replace(&mut parser.diagnostics, ~[]))
+}
+
+/// Parses a sheet as its text arrives. Each piece is parsed up to the end of
+/// the last rule it completes, or the last declaration of a style attribute,
+/// and the rest is kept until a later piece or `finish` completes it
+pub struct SheetParser {
+    priv inline_style: bool,
+    // Text that does not make a complete rule yet
+    priv pending: ~str,
+    // Where the pending text starts in the sheet
+    priv start: SourcePosition,
+    // How much text was pending after the last parse. It is only parsed
+    // again once it has doubled, so that a long @media rule arriving in
+    // many pieces is not parsed over and over
+    priv attempted: uint,
+    // Whether any rule has been parsed, since @charset must come first
+    priv started: bool,
+    priv seen_rule: bool,
+    priv seen_namespace: bool,
+    priv rules: ~[CssRule],
+    priv diagnostics: ~[CssDiagnostic]
+}
+
+impl SheetParser {
+    pub fn new(inline_style: bool) -> SheetParser {
+        SheetParser {
+            inline_style: inline_style,
+            pending: ~"",
+            start: SourcePosition { line: 1, column: 1 },
+            attempted: 0,
+            started: false,
+            seen_rule: false,
+            seen_namespace: false,
+            rules: ~[],
+            diagnostics: ~[]
+        }
+    }
+
+    /// The rules parsed so far. A style attribute is a single style rule with
+    /// no selectors, once any of it has been parsed
+    pub fn rules<'a>(&'a self) -> &'a [CssRule] {
+        self.rules.as_slice()
+    }
+
+    pub fn diagnostics<'a>(&'a self) -> &'a [CssDiagnostic] {
+        self.diagnostics.as_slice()
+    }
+
+    /// Parses what `text` completes, dropping the declarations `check` rejects
+    pub fn push(&mut self, text: &str, check: &fn(CssDeclarationContext, &CssDeclaration) -> bool) {
+        self.pending.push_str(text);
+        if self.pending.len() >= self.attempted * 2 {
+            self.parse(false, check);
+        }
+    }
+
+    /// Parses the last of the text and whatever is still pending, now that
+    /// the sheet has ended
+    pub fn finish(&mut self, text: &str, check: &fn(CssDeclarationContext, &CssDeclaration) -> bool) {
+        self.pending.push_str(text);
+        self.parse(true, check);
+    }
+
+    fn parse(&mut self, last: bool, check: &fn(CssDeclarationContext, &CssDeclaration) -> bool) {
+        let text = util::replace(&mut self.pending, ~"");
+        let mut parser = Parser::new(text, check);
+        parser.seen_rule = self.seen_rule;
+        parser.seen_namespace = self.seen_namespace;
+        parser.namespaces = self.rules.iter().filter_map(|rule| match *rule {
+            CssRuleNamespace(ref rule) if !rule.prefix.is_empty() => Some(rule.prefix.clone()),
+            _ => None
+        }).collect();
+        let mut rules = ~[];
+        let mut declarations = ~[];
+        if self.inline_style {
+            declarations = parser.declarations(CssDeclarationsInStyle, false);
+        } else {
+            rules = parser.stylesheet(!self.started);
+        }
+        let mut diagnostics = util::replace(&mut parser.diagnostics, ~[]);
+        let complete = parser.complete;
+        if !last {
+            rules.truncate(complete.items);
+            declarations.truncate(complete.items);
+            diagnostics.truncate(complete.diagnostics);
+        }
+
+        for diagnostic in diagnostics.mut_iter() {
+            let position = in_sheet(self.start, SourcePosition { line: diagnostic.line, column: diagnostic.column });
+            diagnostic.line = position.line;
+            diagnostic.column = position.column;
+        }
+        self.diagnostics.push_all_move(diagnostics);
+        self.rules.push_all_move(rules);
+        if self.inline_style {
+            if self.rules.is_empty() {
+                self.rules.push(CssRuleStyle(CssStyleRule { selectors: ~[], declarations: ~[] }));
+            }
+            match self.rules[0] {
+                CssRuleStyle(ref mut rule) => rule.declarations.push_all_move(declarations),
+                _ => ()
+            }
+        }
+
+        if !last && complete.pos > 0 {
+            let (offset, position) = parser.ends[complete.pos - 1];
+            self.pending = text.slice_chars(offset, text.char_len()).to_owned();
+            self.start = in_sheet(self.start, position);
+            self.started = true;
+            self.seen_rule = complete.seen_rule;
+            self.seen_namespace = complete.seen_namespace;
+        } else if !last {
+            self.pending = text.clone();
+        }
+        self.attempted = self.pending.len();
+    }
+}
+
+// Where `position`, in text that starts at `start`, is in the whole sheet
+fn in_sheet(start: SourcePosition, position: SourcePosition) -> SourcePosition {
+    if position.line == 1 {
+        SourcePosition { line: start.line, column: start.column + position.column - 1 }
+    } else {
+        SourcePosition { line: start.line + position.line - 1, column: position.column }
+    }
+}
+
+// Which declarations a block holds
+enum BlockContents {
+    Declarations(CssDeclarationContext),
+    Rules,
+    // Kept as written, for an unknown at-rule
+    Verbatim,
+    Unchecked
+}
+
+struct Parser<'self> {
+    // Comments are dropped up front, they never affect recovery
+    tokens: ~[(Token, SourcePosition)],
+    // Where each token ends, in characters and as a position
+    ends: ~[(uint, SourcePosition)],
+    pos: uint,
+    end: SourcePosition,
+    diagnostics: ~[CssDiagnostic],
+    // Whether libcss keeps a declaration that parsed
+    check: &'self fn(CssDeclarationContext, &CssDeclaration) -> bool,
+    // @charset and @import are only allowed before any other rule, and
+    // @namespace before any but those
+    seen_rule: bool,
+    seen_namespace: bool,
+    // The prefixes @namespace rules have declared
+    namespaces: ~[~str],
+    // Set once a rule runs into the end of the text, where more text could
+    // have changed how it parsed
+    reached_end: bool,
+    // How far the parse got before that
+    complete: Checkpoint
+}
+
+// The state after the last rule or declaration that did not reach the end
+struct Checkpoint {
+    // The first token after it
+    pos: uint,
+    // How many rules or declarations there were
+    items: uint,
+    diagnostics: uint,
+    seen_rule: bool,
+    seen_namespace: bool
+}
+
+impl<'self> Parser<'self> {
+    fn new(text: &str, check: &'self fn(CssDeclarationContext, &CssDeclaration) -> bool) -> Parser<'self> {
+        let mut tokenizer = Tokenizer::new(text);
+        let mut tokens = ~[];
+        let mut ends = ~[];
+        loop {
+            match tokenizer.next() {
+                Some((TokenComment(_), _)) => (),
+                Some(token) => {
+                    tokens.push(token);
+                    ends.push((tokenizer.offset(), tokenizer.position()));
+                }
+                None => break
+            }
+        }
+        Parser {
+            tokens: tokens,
+            ends: ends,
+            pos: 0,
+            end: tokenizer.position(),
+            diagnostics: ~[],
+            check: check,
+            seen_rule: false,
+            seen_namespace: false,
+            namespaces: ~[],
+            reached_end: false,
+            complete: Checkpoint { pos: 0, items: 0, diagnostics: 0, seen_rule: false, seen_namespace: false }
+        }
+    }
+
+    // Notes that the rules or declarations so far are complete, unless one
+    // of them ran into the end of the text
+    fn completed(&mut self, items: uint) {
+        if !self.reached_end {
+            self.complete = Checkpoint {
+                pos: self.pos,
+                items: items,
+                diagnostics: self.diagnostics.len(),
+                seen_rule: self.seen_rule,
+                seen_namespace: self.seen_namespace
+            };
+        }
+    }
+
+    fn peek<'a>(&'a self) -> Option<&'a Token> {
+        token_at(self.tokens, self.pos)
+    }
+
+    // A copy of the next token, so the parser can be mutated while matching on it
+    fn current(&self) -> Option<Token> {
+        match self.peek() {
+            Some(token) => Some(token.clone()),
+            None => None
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek() == Some(&TokenWhitespace) {
+            self.pos += 1;
+        }
+    }
+
+    fn report(&mut self, index: uint, message: &str, dropped: CssDropped, severity: CssSeverity) {
+        let (position, token) = if index < self.tokens.len() {
+            let (ref token, position) = self.tokens[index];
+            (position, token.to_css())
+        } else {
+            (self.end, ~"")
+        };
+        self.diagnostics.push(CssDiagnostic {
+            line: position.line,
+            column: position.column,
+            token: token,
+            message: message.to_owned(),
+            dropped: dropped,
+            severity: severity
+        });
+    }
+
+    // @charset is only allowed at the very start, if that is where the
+    // text is from
+    fn stylesheet(&mut self, allow_charset: bool) -> ~[CssRule] {
+        let mut rules = ~[];
+        loop {
+            match self.current() {
+                Some(TokenWhitespace) | Some(TokenCdo) | Some(TokenCdc) => {
+                    self.pos += 1;
+                    continue;
+                }
+                None => return rules,
+                Some(TokenAtKeyword(name)) => {
+                    let name = name.to_ascii_lower();
+                    let index = self.pos;
+                    let allow_import = !self.seen_rule && !self.seen_namespace;
+                    let allow_namespace = !self.seen_rule;
+                    match self.at_rule(name, allow_charset && index == 0, allow_import, allow_namespace) {
+                        Some(rule) => rules.push(rule),
+                        None => ()
+                    }
+                    match name.as_slice() {
+                        "charset" | "import" => (),
+                        "namespace" => self.seen_namespace = true,
+                        _ => self.seen_rule = true
+                    }
+                }
+                Some(_) => {
+                    match self.ruleset() {
+                        Some(rule) => rules.push(rule),
+                        None => ()
+                    }
+                    self.seen_rule = true;
+                }
+            }
+            self.completed(rules.len());
+        }
+    }
+
+    // Consumes up to the end of the enclosing block, which is left in place
+    fn rule_list(&mut self) -> ~[CssRule] {
+        let mut rules = ~[];
+        loop {
+            self.skip_whitespace();
+            let rule = match self.current() {
+                None | Some(TokenCloseBrace) => return rules,
+                Some(TokenAtKeyword(name)) => {
+                    let name = name.to_ascii_lower();
+                    self.at_rule(name, false, false, false)
+                }
+                Some(_) => self.ruleset()
+            };
+            match rule {
+                Some(rule) => rules.push(rule),
+                None => ()
+            }
+        }
+    }
+
+    fn at_rule(&mut self, name: &str, allow_charset: bool, allow_import: bool,
+               allow_namespace: bool) -> Option<CssRule> {
+        let start = self.pos;
+        self.pos += 1;
+        let (prelude_end, terminator) = self.prelude();
+        let prelude = tokens_to_css(self.tokens.slice(start, prelude_end));
+        // Everything after the at-keyword
+        let arguments = tokens_to_css(self.tokens.slice(start + 1, prelude_end));
+        let has_block = terminator == Some(TokenOpenBrace);
+        let first = self.first_significant(start + 1, prelude_end);
+
+        let mut rule = None;
+        let contents = match name {
+            "charset" | "import" | "namespace" if has_block => {
+                self.report(prelude_end, fmt!("@%s can not have a block", name),
+                            CssDroppedRule(prelude.clone()), CssSeverityError);
+                Unchecked
+            }
+            "charset" if !allow_charset => {
+                self.report(start, "@charset is only allowed at the very start of a sheet",
+                            CssDroppedRule(prelude.clone()), CssSeverityError);
+                Unchecked
+            }
+            "import" if !allow_import => {
+                self.report(start, "@import is only allowed before all other rules",
+                            CssDroppedRule(prelude.clone()), CssSeverityError);
+                Unchecked
+            }
+            "import" => {
+                match first {
+                    Some(i) if is_import_target(&self.tokens[i]) => {
+                        rule = Some(CssRuleImport(CssImportRule {
+                            url: self.string_at(i),
+                            media: tokens_to_css(self.tokens.slice(i + 1, prelude_end))
+                        }));
+                    }
+                    _ => self.report(match first { Some(i) => i, None => prelude_end },
+                                     "Expected a string or url() after @import",
+                                     CssDroppedRule(prelude.clone()), CssSeverityError)
+                }
+                Unchecked
+            }
+            "charset" => {
+                // libcss ignores a malformed @charset without complaint
+                match first {
+                    Some(i) if match self.tokens[i] { (TokenString(_), _) => true, _ => false } => {
+                        rule = Some(CssRuleCharset(CssCharsetRule { encoding: self.string_at(i) }));
+                    }
+                    _ => ()
+                }
+                Unchecked
+            }
+            "namespace" if !allow_namespace => {
+                self.report(start, "@namespace is only allowed before all rules but @charset and @import",
+                            CssDroppedRule(prelude.clone()), CssSeverityError);
+                Unchecked
+            }
+            "namespace" => {
+                // An optional prefix, then the URL
+                let (prefix, target) = match first {
+                    Some(i) => match self.tokens[i] {
+                        (TokenIdent(ref prefix), _) => (prefix.clone(), self.first_significant(i + 1, prelude_end)),
+                        _ => (~"", first)
+                    },
+                    None => (~"", None)
+                };
+                match target {
+                    Some(i) if is_import_target(&self.tokens[i]) &&
+                               self.first_significant(i + 1, prelude_end).is_none() => {
+                        if !prefix.is_empty() {
+                            self.namespaces.push(prefix.clone());
+                        }
+                        rule = Some(CssRuleNamespace(CssNamespaceRule { prefix: prefix, url: self.string_at(i) }));
+                    }
+                    _ => self.report(match target { Some(i) => i, None => prelude_end },
+                                     "Expected a string or url() after @namespace",
+                                     CssDroppedRule(prelude.clone()), CssSeverityError)
+                }
+                Unchecked
+            }
+            "media" | "page" | "font-face" if !has_block => {
+                self.report(prelude_end, fmt!("Expected a block after @%s", name),
+                            CssDroppedRule(prelude.clone()), CssSeverityError);
+                Unchecked
+            }
+            "media" => Rules,
+            "page" => Declarations(CssDeclarationsInPage),
+            "font-face" => Declarations(CssDeclarationsInFontFace),
+            _ => {
+                // libcss ignores it, but it stays in the rule tree
+                self.report(start, fmt!("Unknown at-rule @%s", name),
+                            CssDroppedRule(prelude.clone()), CssSeverityWarning);
+                rule = Some(CssRuleUnknown(CssUnknownRule {
+                    name: name.to_owned(),
+                    prelude: arguments.clone(),
+                    block: None
+                }));
+                Verbatim
+            }
+        };
+
+        if has_block {
+            self.pos += 1;
+            rule = match contents {
+                Declarations(context) => {
+                    let declarations = self.declarations(context, true);
+                    Some(if name == "page" {
+                        CssRulePage(CssPageRule { selector: arguments, declarations: declarations })
+                    } else {
+                        CssRuleFontFace(CssFontFaceRule { declarations: declarations })
+                    })
+                }
+                Rules => Some(CssRuleMedia(CssMediaRule { media: arguments, rules: self.rule_list() })),
+                Verbatim => {
+                    let block_start = self.pos;
+                    self.skip_block_contents();
+                    Some(CssRuleUnknown(CssUnknownRule {
+                        name: name.to_owned(),
+                        prelude: arguments,
+                        block: Some(tokens_to_css(self.tokens.slice(block_start, self.pos)))
+                    }))
+                }
+                Unchecked => {
+                    self.skip_block_contents();
+                    None
+                }
+            };
+            self.close_block();
+        }
+        rule
+    }
+
+    // Consumes an at-rule prelude, returning where it ended and the ';' or
+    // '{' that ended it. A ';' is consumed, a '{' is left for the caller
+    fn prelude(&mut self) -> (uint, Option<Token>) {
+        let mut depth = 0;
+        loop {
+            match self.current() {
+                None => {
+                    self.reached_end = true;
+                    return (self.pos, None);
+                }
+                Some(TokenSemicolon) if depth == 0 => {
+                    self.pos += 1;
+                    return (self.pos - 1, Some(TokenSemicolon));
+                }
+                Some(TokenOpenBrace) if depth == 0 => return (self.pos, Some(TokenOpenBrace)),
+                // A stray '}' ends the enclosing block, and this rule with it
+                Some(TokenCloseBrace) if depth == 0 => return (self.pos, None),
+                Some(ref token) => depth = nest(token, depth)
+            }
+            self.pos += 1;
+        }
+    }
+
+    fn ruleset(&mut self) -> Option<CssRule> {
+        let start = self.pos;
+        let mut depth = 0;
+        loop {
+            match self.current() {
+                None => {
+                    self.reached_end = true;
+                    let selector = tokens_to_css(self.tokens.slice_from(start));
+                    let index = self.pos;
+                    self.report(index, "Expected '{' after selector",
+                                CssDroppedRule(selector), CssSeverityError);
+                    return None;
+                }
+                Some(TokenOpenBrace) if depth == 0 => break,
+                Some(ref token) => depth = nest(token, depth)
+            }
+            self.pos += 1;
+        }
+
+        let selector_end = self.pos;
+        let selector = tokens_to_css(self.tokens.slice(start, selector_end));
+        self.pos += 1;
+        let rule = match check_selector(self.tokens.slice(start, selector_end), self.namespaces) {
+            Ok(()) => {
+                let selectors = split_selector_list(self.tokens.slice(start, selector_end));
+                Some(CssRuleStyle(CssStyleRule {
+                    selectors: selectors,
+                    declarations: self.declarations(CssDeclarationsInStyle, true)
+                }))
+            }
+            Err((offset, message)) => {
+                self.report(start + offset, message, CssDroppedRule(selector), CssSeverityError);
+                self.skip_block_contents();
+                None
+            }
+        };
+        self.close_block();
+        rule
+    }
+
+    fn close_block(&mut self) {
+        match self.current() {
+            Some(TokenCloseBrace) => self.pos += 1,
+            _ => {
+                self.reached_end = true;
+                let index = self.pos;
+                self.report(index, "Block is not closed before the end of the sheet",
+                            CssDroppedNothing, CssSeverityWarning);
+            }
+        }
+    }
+
+    // Skips to the '}' that closes the current block
+    fn skip_block_contents(&mut self) {
+        let mut depth = 0;
+        loop {
+            match self.current() {
+                None => {
+                    self.reached_end = true;
+                    return;
+                }
+                Some(TokenCloseBrace) if depth == 0 => return,
+                Some(ref token) => depth = nest(token, depth)
+            }
+            self.pos += 1;
+        }
+    }
+
+    // Skips to just after the ';' ending a declaration, or to the '}' ending the block
+    fn skip_declaration(&mut self) {
+        let mut depth = 0;
+        loop {
+            match self.current() {
+                None => {
+                    self.reached_end = true;
+                    return;
+                }
+                Some(TokenCloseBrace) if depth == 0 => return,
+                Some(TokenSemicolon) if depth == 0 => {
+                    self.pos += 1;
+                    return;
+                }
+                Some(ref token) => depth = nest(token, depth)
+            }
+            self.pos += 1;
+        }
+    }
+
+    // Consumes declarations up to the end of the enclosing block, if any
+    fn declarations(&mut self, context: CssDeclarationContext, in_block: bool) -> ~[CssDeclaration] {
+        let mut declarations = ~[];
+        loop {
+            self.skip_whitespace();
+            let index = self.pos;
+            match self.current() {
+                None => return declarations,
+                Some(TokenCloseBrace) if in_block => return declarations,
+                // Without a block to end there is nothing for it to close,
+                // and leaving it would stop the list going any further
+                Some(TokenCloseBrace) => {
+                    self.report(index, "Unmatched '}'", CssDroppedNothing, CssSeverityError);
+                    self.pos += 1;
+                }
+                Some(TokenSemicolon) => self.pos += 1,
+                Some(TokenIdent(name)) => {
+                    match self.declaration(name, context) {
+                        Some(declaration) => declarations.push(declaration),
+                        None => ()
+                    }
+                }
+                Some(TokenAtKeyword(_)) => {
+                    self.report(index, "At-rules are not allowed among declarations",
+                                CssDroppedNothing, CssSeverityError);
+                    self.skip_declaration();
+                }
+                Some(_) => {
+                    self.skip_declaration();
+                    let text = tokens_to_css(self.tokens.slice(index, self.pos));
+                    self.report(index, "Expected a property name", CssDroppedDeclaration(text),
+                                CssSeverityError);
+                }
+            }
+            if !in_block {
+                self.completed(declarations.len());
+            }
+        }
+    }
+
+    fn declaration(&mut self, name: ~str, context: CssDeclarationContext) -> Option<CssDeclaration> {
+        let name_index = self.pos;
+        self.pos += 1;
+        self.skip_whitespace();
+        if self.peek() != Some(&TokenColon) {
+            let index = self.pos;
+            self.report(index, "Expected ':' after the property name",
+                        CssDroppedDeclaration(name.clone()), CssSeverityError);
+            self.skip_declaration();
+            return None;
+        }
+        self.pos += 1;
+
+        let value_start = self.pos;
+        let mut depth = 0;
+        let mut error = None;
+        loop {
+            match self.current() {
+                None => {
+                    self.reached_end = true;
+                    break;
+                }
+                Some(TokenCloseBrace) | Some(TokenSemicolon) if depth == 0 => break,
+                Some(TokenBadString(_)) if error.is_none() => {
+                    error = Some((self.pos, "String is not closed before the end of the line"));
+                }
+                Some(TokenBadUri(_)) if error.is_none() => {
+                    error = Some((self.pos, "Malformed url()"));
+                }
+                Some(TokenCloseParen) | Some(TokenCloseBracket) if depth == 0 && error.is_none() => {
+                    error = Some((self.pos, "Unmatched closing bracket"));
+                }
+                Some(ref token) => depth = nest(token, depth)
+            }
+            self.pos += 1;
+        }
+        let value_end = self.pos;
+        if self.peek() == Some(&TokenSemicolon) {
+            self.pos += 1;
+        }
+
+        let error = match error {
+            Some(error) => Some(error),
+            None => match check_value(self.tokens.slice(value_start, value_end)) {
+                Some((offset, message)) => Some((value_start + offset, message)),
+                None => None
+            }
+        };
+        match error {
+            Some((index, message)) => {
+                self.report(index, message, CssDroppedDeclaration(name), CssSeverityError);
+                None
+            }
+            None => {
+                let lower = name.to_ascii_lower();
+                let known = match context {
+                    CssDeclarationsInFontFace => FONT_FACE_DESCRIPTORS,
+                    _ => KNOWN_PROPERTIES
+                };
+                if !known.iter().any(|p| *p == lower.as_slice()) {
+                    self.report(name_index, fmt!("Unknown property '%s'", name),
+                                CssDroppedDeclaration(name), CssSeverityWarning);
+                    return None;
+                }
+                let (value, important) = split_important(self.tokens.slice(value_start, value_end));
+                let declaration = CssDeclaration {
+                    property: lower,
+                    value: value,
+                    important: important
+                };
+                if (self.check)(context, &declaration) {
+                    return Some(declaration);
+                }
+                let index = match self.first_significant(value_start, value_end) {
+                    Some(index) => index,
+                    None => value_start
+                };
+                self.report(index, fmt!("Invalid value for '%s'", declaration.property),
+                            CssDroppedDeclaration(name), CssSeverityError);
+                None
+            }
+        }
+    }
+
+    // The text of a string or url() token
+    fn string_at(&self, index: uint) -> ~str {
+        match self.tokens[index] {
+            (TokenString(ref text), _) | (TokenUri(ref text), _) => text.clone(),
+            _ => ~""
+        }
+    }
+
+    fn first_significant(&self, start: uint, end: uint) -> Option<uint> {
+        range(start, end).find(|&i| {
+            let (ref token, _) = self.tokens[i];
+            *token != TokenWhitespace
+        })
+    }
+}
+
+/// Whether libcss keeps an `@font-face` descriptor. It reads `font-family`,
+/// `src`, `font-style` and `font-weight`, and skips the others whatever
+/// their value
+pub fn check_descriptor(declaration: &CssDeclaration) -> bool {
+    let tokens: ~[Token] = Tokenizer::new(declaration.value).filter_map(|(token, _)| {
+        if token.is_trivia() { None } else { Some(token) }
+    }).collect();
+    match declaration.property.as_slice() {
+        "font-family" => !tokens.is_empty() && match tokens[0] {
+            TokenString(_) => tokens.len() == 1,
+            _ => tokens.iter().all(|token| match *token { TokenIdent(_) => true, _ => false })
+        },
+        "src" => is_font_sources(tokens),
+        "font-style" => is_keyword(tokens, ["normal", "italic", "oblique"]),
+        "font-weight" => is_keyword(tokens, ["normal", "bold"]) || tokens.len() == 1 && match tokens[0] {
+            TokenNumber(ref number) => {
+                ["100", "200", "300", "400", "500", "600", "700", "800", "900"].iter().any(|w| *w == number.as_slice())
+            }
+            _ => false
+        },
+        _ => true
+    }
+}
+
+fn is_keyword(tokens: &[Token], keywords: &[&str]) -> bool {
+    tokens.len() == 1 && match tokens[0] {
+        TokenIdent(ref name) => keywords.iter().any(|keyword| name.eq_ignore_ascii_case(*keyword)),
+        _ => false
+    }
+}
+
+// A comma separated list of url() with an optional format(), or local()
+fn is_font_sources(tokens: &[Token]) -> bool {
+    let mut i = 0;
+    loop {
+        if i == tokens.len() {
+            return false;
+        }
+        match tokens[i] {
+            TokenUri(_) => {
+                i += 1;
+                if i < tokens.len() && is_function(&tokens[i], "format") {
+                    match function_end(tokens, i) {
+                        Some(end) => i = end + 1,
+                        None => return false
+                    }
+                }
+            }
+            ref token if is_function(token, "local") => {
+                match function_end(tokens, i) {
+                    Some(end) => i = end + 1,
+                    None => return false
+                }
+            }
+            _ => return false
+        }
+        if i == tokens.len() {
+            return true;
+        }
+        if tokens[i] != TokenComma {
+            return false;
+        }
+        i += 1;
+    }
+}
+
+fn is_function(token: &Token, name: &str) -> bool {
+    match *token {
+        TokenFunction(ref function) => function.eq_ignore_ascii_case(name),
+        _ => false
+    }
+}
+
+// The ')' that closes the function at `start`
+fn function_end(tokens: &[Token], start: uint) -> Option<uint> {
+    let mut depth = 0;
+    for i in range(start + 1, tokens.len()) {
+        match tokens[i] {
+            TokenCloseParen if depth == 0 => return Some(i),
+            ref token => depth = nest(token, depth)
+        }
+    }
+    None
+}
+
+// Expects a value that passed check_value
+fn split_important(tokens: &[(Token, SourcePosition)]) -> (~str, bool) {
+    let bang = range(0, tokens.len()).find(|&i| {
+        let (ref token, _) = tokens[i];
+        *token == TokenDelim('!')
+    });
+    match bang {
+        Some(i) => (tokens_to_css(tokens.slice_to(i)), true),
+        None => (tokens_to_css(tokens), false)
+    }
+}
+
+// Expects a selector list that passed check_selector
+fn split_selector_list(tokens: &[(Token, SourcePosition)]) -> ~[CssSelector] {
+    let mut selectors = ~[];
+    let mut start = 0;
+    let mut depth = 0;
+    for i in range(0, tokens.len() + 1) {
+        let at_comma = i < tokens.len() && match tokens[i] {
+            (TokenComma, _) if depth == 0 => true,
+            (ref token, _) => {
+                depth = nest(token, depth);
+                false
+            }
+        };
+        if at_comma || i == tokens.len() {
+            let selector = tokens.slice(start, i);
+            selectors.push(CssSelector {
+                text: tokens_to_css(selector),
+                specificity: specificity(selector)
+            });
+            start = i + 1;
+        }
+    }
+    selectors
+}
+
+// The pseudo-elements CSS 2.1 allows to be written with a single colon
+static LEGACY_PSEUDO_ELEMENTS: &'static [&'static str] = &["after", "before", "first-letter", "first-line"];
+
+/// The specificity of a single complex selector, as CSS 2.1 section 6.4.3
+/// and Selectors Level 3 compute it: `:not()` counts as its argument
+pub fn specificity(tokens: &[(Token, SourcePosition)]) -> CssSpecificity {
+    let mut result = CssSpecificity { ids: 0, classes: 0, elements: 0 };
+    let mut i = 0;
+    while i < tokens.len() {
+        match tokens[i] {
+            (TokenHash(_), _) => result.ids += 1,
+            // A namespace prefix
+            (TokenIdent(_), _) if token_at(tokens, i + 1) == Some(&TokenDelim('|')) => (),
+            (TokenIdent(_), _) => result.elements += 1,
+            (TokenDelim('.'), _) => {
+                result.classes += 1;
+                // Skip the class name, which is not a type selector
+                i += 1;
+            }
+            (TokenOpenBracket, _) => {
+                result.classes += 1;
+                while i < tokens.len() && match tokens[i] { (TokenCloseBracket, _) => false, _ => true } {
+                    i += 1;
+                }
+            }
+            (TokenColon, _) => {
+                let double = token_at(tokens, i + 1) == Some(&TokenColon);
+                if double {
+                    i += 1;
+                }
+                match token_at(tokens, i + 1) {
+                    Some(&TokenIdent(ref name)) => {
+                        let name = name.to_ascii_lower();
+                        if double || LEGACY_PSEUDO_ELEMENTS.iter().any(|p| *p == name.as_slice()) {
+                            result.elements += 1;
+                        } else {
+                            result.classes += 1;
+                        }
+                        i += 1;
+                    }
+                    Some(&TokenFunction(ref name)) => {
+                        let is_not = name.eq_ignore_ascii_case("not");
+                        let open = i + 1;
+                        let mut close = open + 1;
+                        let mut depth = 0;
+                        while close < tokens.len() {
+                            match tokens[close] {
+                                (TokenCloseParen, _) if depth == 0 => break,
+                                (ref token, _) => depth = nest(token, depth)
+                            }
+                            close += 1;
+                        }
+                        if is_not {
+                            let inner = specificity(tokens.slice(open + 1, close));
+                            result.ids += inner.ids;
+                            result.classes += inner.classes;
+                            result.elements += inner.elements;
+                        } else {
+                            result.classes += 1;
+                        }
+                        i = close;
+                    }
+                    _ => ()
+                }
+            }
+            _ => ()
+        }
+        i += 1;
+    }
+    result
+}
+
+fn is_import_target(token: &(Token, SourcePosition)) -> bool {
+    match *token {
+        (TokenString(_), _) | (TokenUri(_), _) => true,
+        _ => false
+    }
+}
+
+// Tracks the nesting of (), [] and {} inside a component value
+pub fn nest(token: &Token, depth: uint) -> uint {
+    match *token {
+        TokenOpenParen | TokenOpenBracket | TokenOpenBrace | TokenFunction(_) => depth + 1,
+        TokenCloseParen | TokenCloseBracket | TokenCloseBrace if depth > 0 => depth - 1,
+        _ => depth
+    }
+}
+
+// Checks what is left of a value once brackets and bad tokens are ruled out:
+// that it is not empty and that '!' only introduces a trailing 'important'
+pub fn check_value(tokens: &[(Token, SourcePosition)]) -> Option<(uint, &'static str)> {
+    let significant: ~[uint] = range(0, tokens.len()).filter(|&i| {
+        let (ref token, _) = tokens[i];
+        *token != TokenWhitespace
+    }).collect();
+
+    if significant.is_empty() {
+        return Some((tokens.len(), "Missing value"));
+    }
+
+    for (n, &i) in significant.iter().enumerate() {
+        let (ref token, _) = tokens[i];
+        if *token != TokenDelim('!') {
+            continue;
+        }
+        if n == 0 {
+            return Some((i, "Missing value"));
+        }
+        let important = n + 2 == significant.len() && match tokens[significant[n + 1]] {
+            (TokenIdent(ref ident), _) => ident.eq_ignore_ascii_case("important"),
+            _ => false
+        };
+        if !important {
+            return Some((i, "'!' must be followed by 'important' at the end of a value"));
+        }
+    }
+    None
+}
+
+/// Checks a selector group against the selectors libcss understands. On
+/// error gives the index of the offending token, which may be the length of
+/// `tokens` if the selector ended too soon
+pub fn check_selector(tokens: &[(Token, SourcePosition)], namespaces: &[~str]) -> Result<(), (uint, &'static str)> {
+    let mut i = 0;
+    loop {
+        skip_selector_whitespace(tokens, &mut i);
+        loop {
+            match check_compound_selector(tokens, &mut i, namespaces) {
+                Ok(()) => (),
+                Err(e) => return Err(e)
+            }
+            let had_whitespace = skip_selector_whitespace(tokens, &mut i);
+            if i == tokens.len() {
+                return Ok(());
+            }
+            match tokens[i] {
+                (TokenDelim('>'), _) | (TokenDelim('+'), _) | (TokenDelim('~'), _) => {
+                    i += 1;
+                    skip_selector_whitespace(tokens, &mut i);
+                }
+                (TokenComma, _) => {
+                    i += 1;
+                    break;
+                }
+                _ if had_whitespace => (),
+                _ => return Err((i, "Unexpected token in selector"))
+            }
+        }
+    }
+}
+
+fn skip_selector_whitespace(tokens: &[(Token, SourcePosition)], i: &mut uint) -> bool {
+    let start = *i;
+    while *i < tokens.len() && match tokens[*i] { (TokenWhitespace, _) => true, _ => false } {
+        *i += 1;
+    }
+    *i > start
+}
+
+fn check_compound_selector(tokens: &[(Token, SourcePosition)], i: &mut uint,
+                           namespaces: &[~str]) -> Result<(), (uint, &'static str)> {
+    let start = *i;
+    match check_qualified_name(tokens, i, true, namespaces) {
+        Ok(()) => (),
+        Err(e) => return Err(e)
+    }
+    while *i < tokens.len() {
+        match tokens[*i] {
+            (TokenHash(ref name), _) => {
+                if !is_identifier(*name) {
+                    return Err((*i, "An ID selector must be a valid identifier"));
+                }
+                *i += 1;
+            }
+            (TokenDelim('.'), _) => {
+                *i += 1;
+                match token_at(tokens, *i) {
+                    Some(&TokenIdent(_)) => *i += 1,
+                    _ => return Err((*i, "Expected a class name after '.'"))
+                }
+            }
+            (TokenOpenBracket, _) => {
+                match check_attribute_selector(tokens, i, namespaces) {
+                    Ok(()) => (),
+                    Err(e) => return Err(e)
+                }
+            }
+            (TokenColon, _) => {
+                *i += 1;
+                if token_at(tokens, *i) == Some(&TokenColon) {
+                    *i += 1;
+                }
+                match token_at(tokens, *i) {
+                    Some(&TokenIdent(_)) => *i += 1,
+                    Some(&TokenFunction(_)) => {
+                        let function = *i;
+                        let mut depth = 0;
+                        *i += 1;
+                        loop {
+                            match token_at(tokens, *i) {
+                                None => return Err((function, "Pseudo-class arguments are not closed")),
+                                Some(&TokenCloseParen) if depth == 0 => break,
+                                Some(token) => depth = nest(token, depth)
+                            }
+                            *i += 1;
+                        }
+                        *i += 1;
+                    }
+                    _ => return Err((*i, "Expected a pseudo-class name after ':'"))
+                }
+            }
+            (TokenIdent(_), _) | (TokenDelim('*'), _) => {
+                return Err((*i, "A type selector must come first in a compound selector"));
+            }
+            _ => break
+        }
+    }
+    if *i == start {
+        Err((*i, "Expected a selector"))
+    } else {
+        Ok(())
+    }
+}
+
+// A type selector or attribute name, with an optional namespace prefix as in
+// "svg|rect", "*|rect" or "|rect", if there is one
+fn check_qualified_name(tokens: &[(Token, SourcePosition)], i: &mut uint, allow_star: bool,
+                        namespaces: &[~str]) -> Result<(), (uint, &'static str)> {
+    let has_prefix = match token_at(tokens, *i) {
+        Some(&TokenIdent(_)) | Some(&TokenDelim('*')) => true,
+        _ => false
+    };
+    let bar = if has_prefix { *i + 1 } else { *i };
+    if token_at(tokens, bar) == Some(&TokenDelim('|')) {
+        match token_at(tokens, *i) {
+            Some(&TokenIdent(ref prefix)) if !namespaces.iter().any(|n| n == prefix) => {
+                return Err((*i, "Namespace prefix has not been declared"));
+            }
+            _ => ()
+        }
+        *i = bar + 1;
+        match token_at(tokens, *i) {
+            Some(&TokenIdent(_)) => *i += 1,
+            Some(&TokenDelim('*')) if allow_star => *i += 1,
+            _ => return Err((*i, "Expected a name after '|'"))
+        }
+    } else {
+        match token_at(tokens, *i) {
+            Some(&TokenIdent(_)) => *i += 1,
+            Some(&TokenDelim('*')) if allow_star => *i += 1,
+            _ => ()
+        }
+    }
+    Ok(())
+}
+
+fn check_attribute_selector(tokens: &[(Token, SourcePosition)], i: &mut uint,
+                            namespaces: &[~str]) -> Result<(), (uint, &'static str)> {
+    let open = *i;
+    *i += 1;
+    skip_selector_whitespace(tokens, i);
+    let name = *i;
+    match check_qualified_name(tokens, i, false, namespaces) {
+        Ok(()) if *i > name => (),
+        Ok(()) => return Err((*i, "Expected an attribute name")),
+        Err(e) => return Err(e)
+    }
+    skip_selector_whitespace(tokens, i);
+    match token_at(tokens, *i) {
+        Some(&TokenDelim('=')) | Some(&TokenIncludes) | Some(&TokenDashMatch) |
+        Some(&TokenPrefixMatch) | Some(&TokenSuffixMatch) | Some(&TokenSubstringMatch) => {
+            *i += 1;
+            skip_selector_whitespace(tokens, i);
+            match token_at(tokens, *i) {
+                Some(&TokenIdent(_)) | Some(&TokenString(_)) => *i += 1,
+                _ => return Err((*i, "Expected an identifier or string as the attribute value"))
+            }
+            skip_selector_whitespace(tokens, i);
+        }
+        _ => ()
+    }
+    match token_at(tokens, *i) {
+        Some(&TokenCloseBracket) => {
+            *i += 1;
+            Ok(())
+        }
+        None => Err((open, "Attribute selector is not closed")),
+        Some(_) => Err((*i, "Expected ']'"))
+    }
+}
+
+pub fn token_at<'a>(tokens: &'a [(Token, SourcePosition)], i: uint) -> Option<&'a Token> {
+    if i < tokens.len() {
+        let (ref token, _) = tokens[i];
+        Some(token)
+    } else {
+        None
+    }
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut tokenizer = Tokenizer::new(name);
+    match (tokenizer.next(), tokenizer.next()) {
+        (Some((TokenIdent(ref ident), _)), None) => ident.as_slice() == name,
+        _ => false
+    }
+}
+
+/// The tokens as text, trimmed and with whitespace collapsed
+pub fn tokens_to_css(tokens: &[(Token, SourcePosition)]) -> ~str {
+    let mut text = ~"";
+    for &(ref token, _) in tokens.iter() {
+        if token.is_trivia() {
+            if !text.is_empty() && !text.ends_with(" ") {
+                text.push_char(' ');
+            }
+        } else {
+            text.push_str(token.to_css());
+        }
+    }
+    text.trim_right().to_owned()
+}
+
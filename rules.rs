@@ -0,0 +1,268 @@
+// Copyright 2013 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+/*!
+
+A read-only rule tree for a stylesheet, in the shape of the CSSOM.
+
+libcss does not let its parsed rules be inspected, so the tree is derived
+from the source of the sheet by `parser.rs`, as the sheet arrives. Rules and
+declarations dropped by error recovery are not in it, and neither are the
+declarations libcss dropped for values it could not parse. Unknown at-rules,
+which libcss ignores, are kept as written so that the sheet can be written
+back out.
+
+*/
+
+use std::vec;
+use media::MediaQueryList;
+
+#[deriving(Eq, Clone)]
+pub enum CssRule {
+    CssRuleStyle(CssStyleRule),
+    CssRuleMedia(CssMediaRule),
+    CssRuleImport(CssImportRule),
+    CssRulePage(CssPageRule),
+    CssRuleFontFace(CssFontFaceRule),
+    CssRuleCharset(CssCharsetRule),
+    CssRuleNamespace(CssNamespaceRule),
+    CssRuleUnknown(CssUnknownRule)
+}
+
+#[deriving(Eq, Clone)]
+pub struct CssStyleRule {
+    selectors: ~[CssSelector],
+    declarations: ~[CssDeclaration]
+}
+
+#[deriving(Eq, Clone)]
+pub struct CssMediaRule {
+    // The media query list as written
+    media: ~str,
+    rules: ~[CssRule]
+}
+
+#[deriving(Eq, Clone)]
+pub struct CssImportRule {
+    // As written, not resolved against the sheet's URL
+    url: ~str,
+    media: ~str
+}
+
+#[deriving(Eq, Clone)]
+pub struct CssPageRule {
+    // e.g. ":first", or empty for every page
+    selector: ~str,
+    declarations: ~[CssDeclaration]
+}
+
+#[deriving(Eq, Clone)]
+pub struct CssFontFaceRule {
+    declarations: ~[CssDeclaration]
+}
+
+#[deriving(Eq, Clone)]
+pub struct CssCharsetRule {
+    encoding: ~str
+}
+
+#[deriving(Eq, Clone)]
+pub struct CssNamespaceRule {
+    // Empty for the default namespace
+    prefix: ~str,
+    url: ~str
+}
+
+#[deriving(Eq, Clone)]
+pub struct CssUnknownRule {
+    // Lowercased, without the '@'
+    name: ~str,
+    prelude: ~str,
+    // What was between the braces, or None if the rule ended with ';'
+    block: Option<~str>
+}
+
+#[deriving(Eq, Clone)]
+pub struct CssSelector {
+    // One complex selector from a selector list, e.g. "div > p.note"
+    text: ~str,
+    specificity: CssSpecificity
+}
+
+// Compared as CSS 2.1 section 6.4.3 says, IDs first
+#[deriving(Eq, Ord, Clone)]
+pub struct CssSpecificity {
+    ids: uint,
+    classes: uint,
+    elements: uint
+}
+
+#[deriving(Eq, Clone)]
+pub struct CssDeclaration {
+    // Lowercased
+    property: ~str,
+    // Without the !important
+    value: ~str,
+    important: bool
+}
+
+impl CssMediaRule {
+    pub fn media_queries(&self) -> MediaQueryList {
+        MediaQueryList::parse(self.media.as_slice())
+    }
+}
+
+impl CssImportRule {
+    pub fn media_queries(&self) -> MediaQueryList {
+        MediaQueryList::parse(self.media.as_slice())
+    }
+}
+
+impl CssRule {
+    /// The declarations directly in this rule, for the kinds that have them
+    pub fn declarations<'a>(&'a self) -> Option<&'a [CssDeclaration]> {
+        match *self {
+            CssRuleStyle(ref rule) => Some(rule.declarations.as_slice()),
+            CssRulePage(ref rule) => Some(rule.declarations.as_slice()),
+            CssRuleFontFace(ref rule) => Some(rule.declarations.as_slice()),
+            _ => None
+        }
+    }
+
+    fn declarations_mut<'a>(&'a mut self) -> Option<&'a mut ~[CssDeclaration]> {
+        match *self {
+            CssRuleStyle(ref mut rule) => Some(&mut rule.declarations),
+            CssRulePage(ref mut rule) => Some(&mut rule.declarations),
+            CssRuleFontFace(ref mut rule) => Some(&mut rule.declarations),
+            _ => None
+        }
+    }
+
+    /// Replaces the declaration for the same property, keeping its place, or
+    /// appends it. False if this kind of rule has no declarations
+    pub fn set_declaration(&mut self, declaration: CssDeclaration) -> bool {
+        match self.declarations_mut() {
+            Some(declarations) => {
+                match declarations.iter().position(|d| d.property == declaration.property) {
+                    Some(i) => declarations[i] = declaration,
+                    None => declarations.push(declaration)
+                }
+                true
+            }
+            None => false
+        }
+    }
+
+    /// Removes the declaration for `property`, which must be lowercase.
+    /// False if there was none
+    pub fn remove_declaration(&mut self, property: &str) -> bool {
+        match self.declarations_mut() {
+            Some(declarations) => {
+                match declarations.iter().position(|d| d.property.as_slice() == property) {
+                    Some(i) => {
+                        declarations.remove(i);
+                        true
+                    }
+                    None => false
+                }
+            }
+            None => false
+        }
+    }
+
+    /// The rules nested in this one, if it is a grouping rule
+    pub fn child_rules<'a>(&'a self) -> Option<&'a [CssRule]> {
+        match *self {
+            CssRuleMedia(ref rule) => Some(rule.rules.as_slice()),
+            _ => None
+        }
+    }
+}
+
+/// The rule that `CssRuleIterator` would give `n`th, for changing it
+pub fn nth_rule_mut<'a>(rules: &'a mut [CssRule], n: &mut uint) -> Option<&'a mut CssRule> {
+    for rule in rules.mut_iter() {
+        if *n == 0 {
+            return Some(rule);
+        }
+        *n -= 1;
+        match *rule {
+            CssRuleMedia(ref mut media) => {
+                match nth_rule_mut(media.rules, n) {
+                    Some(found) => return Some(found),
+                    None => ()
+                }
+            }
+            _ => ()
+        }
+    }
+    None
+}
+
+/// Walks a rule list depth first, visiting grouping rules before the rules
+/// inside them
+pub struct CssRuleIterator<'self> {
+    priv stack: ~[vec::VecIterator<'self, CssRule>]
+}
+
+impl<'self> CssRuleIterator<'self> {
+    pub fn new(rules: &'self [CssRule]) -> CssRuleIterator<'self> {
+        CssRuleIterator {
+            stack: ~[rules.iter()]
+        }
+    }
+}
+
+impl<'self> Iterator<&'self CssRule> for CssRuleIterator<'self> {
+    fn next(&mut self) -> Option<&'self CssRule> {
+        loop {
+            let depth = self.stack.len();
+            if depth == 0 {
+                return None;
+            }
+            match self.stack[depth - 1].next() {
+                Some(rule) => {
+                    match rule.child_rules() {
+                        Some(children) => self.stack.push(children.iter()),
+                        None => ()
+                    }
+                    return Some(rule);
+                }
+                None => {
+                    self.stack.pop();
+                }
+            }
+        }
+    }
+}
+
+/// Every style rule in a rule list, including those inside `@media` rules
+pub struct CssStyleRuleIterator<'self> {
+    priv rules: CssRuleIterator<'self>
+}
+
+impl<'self> CssStyleRuleIterator<'self> {
+    pub fn new(rules: &'self [CssRule]) -> CssStyleRuleIterator<'self> {
+        CssStyleRuleIterator {
+            rules: CssRuleIterator::new(rules)
+        }
+    }
+}
+
+impl<'self> Iterator<&'self CssStyleRule> for CssStyleRuleIterator<'self> {
+    fn next(&mut self) -> Option<&'self CssStyleRule> {
+        loop {
+            match self.rules.next() {
+                Some(&CssRuleStyle(ref rule)) => return Some(rule),
+                Some(_) => (),
+                None => return None
+            }
+        }
+    }
+}
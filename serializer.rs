@@ -0,0 +1,229 @@
+// Copyright 2013 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+/*!
+
+Turns a rule tree back into CSS text.
+
+The output is canonical: comments are gone, whitespace is normalized and
+strings and URLs are double quoted. Parsing it again gives a rule tree that
+serializes to the same text, in either mode.
+
+*/
+
+use tokenizer::*;
+use parser::nest;
+use rules::*;
+
+#[deriving(Eq, Clone)]
+pub enum CssSerializeMode {
+    // One declaration per line, nested rules indented
+    CssSerializePretty,
+    // No whitespace that can be left out
+    CssSerializeMinified
+}
+
+static INDENT: &'static str = "    ";
+
+pub fn serialize_rules(rules: &[CssRule], mode: CssSerializeMode) -> ~str {
+    let mut out = ~"";
+    write_rules(&mut out, rules, mode, 0);
+    out
+}
+
+/// A declaration list without braces, like a style attribute
+pub fn serialize_declarations(declarations: &[CssDeclaration], mode: CssSerializeMode) -> ~str {
+    let mut out = ~"";
+    for (i, declaration) in declarations.iter().enumerate() {
+        if i > 0 {
+            out.push_str(if mode == CssSerializePretty { "; " } else { ";" });
+        }
+        write_declaration(&mut out, declaration, mode);
+    }
+    out
+}
+
+fn write_rules(out: &mut ~str, rules: &[CssRule], mode: CssSerializeMode, depth: uint) {
+    for rule in rules.iter() {
+        write_rule(out, rule, mode, depth);
+    }
+}
+
+fn write_rule(out: &mut ~str, rule: &CssRule, mode: CssSerializeMode, depth: uint) {
+    indent(out, mode, depth);
+    match *rule {
+        CssRuleCharset(ref rule) => {
+            out.push_str(fmt!("@charset %s;", serialize_string(rule.encoding)));
+        }
+        CssRuleImport(ref rule) => {
+            out.push_str(fmt!("@import url(%s)", serialize_string(rule.url)));
+            if !rule.media.is_empty() {
+                out.push_char(' ');
+                out.push_str(format_tokens(rule.media, mode, false));
+            }
+            out.push_char(';');
+        }
+        CssRuleStyle(ref rule) => {
+            let selectors: ~[~str] = rule.selectors.iter().map(|selector| {
+                format_tokens(selector.text, mode, true)
+            }).collect();
+            out.push_str(selectors.connect(if mode == CssSerializePretty { ", " } else { "," }));
+            write_declaration_block(out, rule.declarations, mode, depth);
+        }
+        CssRuleMedia(ref rule) => {
+            out.push_str("@media ");
+            out.push_str(format_tokens(rule.media, mode, false));
+            open_block(out, mode);
+            write_rules(out, rule.rules, mode, depth + 1);
+            close_block(out, mode, depth);
+        }
+        CssRulePage(ref rule) => {
+            out.push_str("@page");
+            if !rule.selector.is_empty() {
+                out.push_char(' ');
+                out.push_str(format_tokens(rule.selector, mode, true));
+            }
+            write_declaration_block(out, rule.declarations, mode, depth);
+        }
+        CssRuleFontFace(ref rule) => {
+            out.push_str("@font-face");
+            write_declaration_block(out, rule.declarations, mode, depth);
+        }
+        CssRuleNamespace(ref rule) => {
+            out.push_str("@namespace ");
+            if !rule.prefix.is_empty() {
+                out.push_str(serialize_identifier(rule.prefix));
+                out.push_char(' ');
+            }
+            out.push_str(fmt!("url(%s);", serialize_string(rule.url)));
+        }
+        CssRuleUnknown(ref rule) => {
+            // Written as it was parsed, since what it means is not known
+            out.push_char('@');
+            out.push_str(serialize_identifier(rule.name));
+            if !rule.prelude.is_empty() {
+                out.push_char(' ');
+                out.push_str(rule.prelude);
+            }
+            match rule.block {
+                Some(ref block) if mode == CssSerializeMinified => out.push_str(fmt!("{%s}", *block)),
+                Some(ref block) if block.is_empty() => out.push_str(" {}"),
+                Some(ref block) => out.push_str(fmt!(" { %s }", *block)),
+                None => out.push_char(';')
+            }
+        }
+    }
+    if mode == CssSerializePretty {
+        out.push_char('\n');
+    }
+}
+
+fn write_declaration_block(out: &mut ~str, declarations: &[CssDeclaration],
+                           mode: CssSerializeMode, depth: uint) {
+    open_block(out, mode);
+    for (i, declaration) in declarations.iter().enumerate() {
+        match mode {
+            CssSerializePretty => {
+                indent(out, mode, depth + 1);
+                write_declaration(out, declaration, mode);
+                out.push_str(";\n");
+            }
+            CssSerializeMinified => {
+                // The last declaration needs no ';'
+                if i > 0 {
+                    out.push_char(';');
+                }
+                write_declaration(out, declaration, mode);
+            }
+        }
+    }
+    close_block(out, mode, depth);
+}
+
+fn write_declaration(out: &mut ~str, declaration: &CssDeclaration, mode: CssSerializeMode) {
+    let pretty = mode == CssSerializePretty;
+    out.push_str(serialize_identifier(declaration.property));
+    out.push_str(if pretty { ": " } else { ":" });
+    out.push_str(format_tokens(declaration.value, mode, false));
+    if declaration.important {
+        out.push_str(if pretty { " !important" } else { "!important" });
+    }
+}
+
+fn open_block(out: &mut ~str, mode: CssSerializeMode) {
+    out.push_str(if mode == CssSerializePretty { " {\n" } else { "{" });
+}
+
+fn close_block(out: &mut ~str, mode: CssSerializeMode, depth: uint) {
+    indent(out, mode, depth);
+    out.push_char('}');
+}
+
+fn indent(out: &mut ~str, mode: CssSerializeMode, depth: uint) {
+    if mode == CssSerializePretty {
+        for _ in range(0, depth) {
+            out.push_str(INDENT);
+        }
+    }
+}
+
+// Rewrites a selector, value or media query list with normalized whitespace.
+// Whitespace between two tokens is kept as a single space, except next to
+// brackets, commas and, in selectors, combinators, where it never matters
+fn format_tokens(text: &str, mode: CssSerializeMode, selector: bool) -> ~str {
+    let pretty = mode == CssSerializePretty;
+    let mut out = ~"";
+    // Whether whitespace came before the current token
+    let mut space = false;
+    // Whether the current token attaches to the previous one regardless
+    let mut glue = true;
+    let mut depth = 0;
+    for (token, _) in Tokenizer::new(text) {
+        if token.is_trivia() {
+            space = true;
+            continue;
+        }
+        let combinator = selector && depth == 0 && match token {
+            TokenDelim('>') | TokenDelim('+') | TokenDelim('~') => true,
+            _ => false
+        };
+        match token {
+            _ if combinator => {
+                out.push_str(if pretty { fmt!(" %s ", token.to_css()) } else { token.to_css() });
+                glue = true;
+            }
+            TokenComma => {
+                out.push_str(if pretty { ", " } else { "," });
+                glue = true;
+            }
+            TokenCloseParen | TokenCloseBracket => {
+                out.push_str(token.to_css());
+                glue = false;
+            }
+            TokenDelim('=') | TokenIncludes | TokenDashMatch |
+            TokenPrefixMatch | TokenSuffixMatch | TokenSubstringMatch => {
+                out.push_str(token.to_css());
+                glue = true;
+            }
+            _ => {
+                if space && !glue {
+                    out.push_char(' ');
+                }
+                out.push_str(token.to_css());
+                glue = match token {
+                    TokenFunction(_) | TokenOpenParen | TokenOpenBracket => true,
+                    _ => false
+                };
+            }
+        }
+        depth = nest(&token, depth);
+        space = false;
+    }
+    out
+}
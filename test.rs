@@ -15,39 +15,19 @@ mod example1 {
     use types::*;
     use hint::*;
     use select::*;
-    use util::VoidPtrLike;
     use wapcaplet::LwcString;
-    use std::libc;
-    use std::cast;
 
     struct MyDomNode {
         name: @LwcString
     }
 
-    impl VoidPtrLike for MyDomNode {
-        fn from_void_ptr(node: *libc::c_void) -> MyDomNode {
-            assert!(node.is_not_null());
-            MyDomNode {
-                name: unsafe {
-                    let box = cast::transmute(node);
-                    cast::bump_box_refcount(box);
-                    box
-                }
-            }
-        }
-
-        fn to_void_ptr(&self) -> *libc::c_void {
-            unsafe { cast::transmute(self.name) }
-        }
-    }
-
     #[test]
     fn run() {
         use super::super::stylesheet::{CssUrlResolutionFn, CssStylesheetParams, CssStylesheet,
-                                       css_stylesheet_create, CssStylesheetParamsVersion1};
+                                       css_stylesheet_create};
         use super::super::computed::CssComputedStyle;
         use super::super::values::{CssColorColor, CssColorInherit};
-        use super::super::ll::types::{CSS_ORIGIN_AUTHOR, CSS_MEDIA_ALL, CSS_MEDIA_SCREEN};
+        use super::super::media::{CssMedia, CssMediaScreen};
         use super::super::conversions::ToLl;
         use wapcaplet::{LwcString, from_rust_string};
 
@@ -57,21 +37,13 @@ mod example1 {
 
         let resolve: CssUrlResolutionFn = |a,b| resolve_url(a, b);
 
-        let params: CssStylesheetParams = CssStylesheetParams {
-            params_version: CssStylesheetParamsVersion1,
-            level: CssLevel21,
-            charset: ~"UTF-8",
-            url: ~"foo",
-            title: ~"foo",
-            allow_quirks: false,
-            inline_style: false,
-            resolve: Some(resolve),
-            import: None,
-            color: None,
-            font: None,
-        };
+        let params: CssStylesheetParams = CssStylesheetParams::new("foo")
+            .level(CssLevel21)
+            .charset("UTF-8")
+            .title("foo")
+            .resolver(resolve);
 
-        let mut sheet: CssStylesheet = css_stylesheet_create(&params);
+        let mut sheet: CssStylesheet = css_stylesheet_create(&params).unwrap();
         debug!("stylesheet: %?", sheet);
         debug!("stylesheet size: %?", sheet.size());
 
@@ -82,9 +54,10 @@ mod example1 {
 
         let mut select_ctx: CssSelectCtx = css_select_ctx_create();
         assert!(select_ctx.count_sheets() == 0);
-        select_ctx.append_sheet(sheet, CSS_ORIGIN_AUTHOR, CSS_MEDIA_ALL);
+        select_ctx.append_sheet(sheet, CssOriginAuthor, CssMedia::all());
         debug!("count sheets: %?", select_ctx.count_sheets());
         assert!(select_ctx.count_sheets() == 1);
+        assert!(select_ctx.sheet_origin(0) == CssOriginAuthor);
 
         for hh in range(1u, 7u) {
             let element = fmt!("h%u", hh);
@@ -92,7 +65,7 @@ mod example1 {
             let node = MyDomNode { name: element_name };
             let select_handler = SelectHandler { bogus: () };
             let style: CssSelectResults = select_ctx.select_style(&node,
-                                                                  CSS_MEDIA_SCREEN,
+                                                                  CssMedia::single(CssMediaScreen),
                                                                   None,
                                                                   &select_handler);
 
@@ -166,29 +139,1348 @@ mod example1 {
 fn test_arc() {
     use extra::arc::Arc;
     use stylesheet::*;
-    use types::CssLevel21;
+    use select::css_select_ctx_create;
+    use media::CssMedia;
+    use types::{CssLevel21, CssOriginUserAgent};
     use wapcaplet::LwcString;
     use super::CssResult;
 
     let resolve: CssUrlResolutionFn = |a,b| resolve_url(a, b);
-    let params: CssStylesheetParams = CssStylesheetParams {
-        params_version: CssStylesheetParamsVersion1,
-        level: CssLevel21,
-        charset: ~"UTF-8",
-        url: ~"foo",
-        title: ~"foo",
-        allow_quirks: false,
-        inline_style: false,
-        resolve: Some(resolve),
-        import: None,
-        color: None,
-        font: None,
+    let params: CssStylesheetParams = CssStylesheetParams::new("foo")
+        .level(CssLevel21)
+        .charset("UTF-8")
+        .title("foo")
+        .resolver(resolve);
+
+    let sheet: CssStylesheet = css_stylesheet_create(&params).unwrap();
+    let arc: CssSharedStylesheet = Arc::new(sheet);
+
+    // The same parsed sheet can back several selection contexts
+    let mut ctx1 = css_select_ctx_create();
+    let mut ctx2 = css_select_ctx_create();
+    ctx1.append_shared_sheet(arc.clone(), CssOriginUserAgent, CssMedia::all());
+    ctx2.append_shared_sheet(arc.clone(), CssOriginUserAgent, CssMedia::all());
+    assert!(ctx1.get_sheet(0).ll_sheet() == ctx2.get_sheet(0).ll_sheet());
+    let removed = ctx1.remove_sheet(0);
+    assert!(removed.get().ll_sheet() == arc.get().ll_sheet());
+    assert!(ctx1.count_sheets() == 0);
+    assert!(ctx2.count_sheets() == 1);
+
+    fn resolve_url(_base: &str, _rel: &LwcString) -> CssResult<LwcString> {
+        fail!(~"resolving url");
+    }
+}
+
+#[test]
+fn test_libcss_lock() {
+    use util::with_libcss_lock;
+    use std::comm::stream;
+    use std::task::spawn;
+
+    // Taken again by the task holding it, as from a handler callback
+    let n = do with_libcss_lock { do with_libcss_lock { 1 } };
+    assert!(n == 1);
+    // and released once the outermost call returns, for other tasks to take
+    let (port, chan) = stream();
+    do spawn {
+        chan.send(do with_libcss_lock { 2 });
+    }
+    assert!(port.recv() == 2);
+}
+
+#[test]
+fn test_params_builder() {
+    use stylesheet::{CssStylesheetParams, CssImportNotificationFn, css_stylesheet_create};
+    use types::CssLevel3;
+    use errors::CssBadParm;
+
+    let params = CssStylesheetParams::new("http://example.com/a.css").level(CssLevel3).quirks(true);
+    assert!(params.get_url() == "http://example.com/a.css");
+    assert!(params.allows_quirks());
+    assert!(params.get_charset().is_none());
+    assert!(params.validate().is_ok());
+
+    let titled_inline = CssStylesheetParams::new("").inline_style(true).title("alternate");
+    assert!(match titled_inline.validate() { Err(CssBadParm) => true, _ => false });
+    let empty_charset = CssStylesheetParams::new("").charset("");
+    assert!(match empty_charset.validate() { Err(CssBadParm) => true, _ => false });
+    // Bad params are an error, not a failure
+    assert!(match css_stylesheet_create(&empty_charset) { Err(CssBadParm) => true, _ => false });
+    let import: CssImportNotificationFn = |_, _| Ok(());
+    let importing_inline = CssStylesheetParams::new("").inline_style(true).importer(import);
+    assert!(match importing_inline.validate() { Err(CssBadParm) => true, _ => false });
+}
+
+#[test]
+fn test_importer() {
+    use stylesheet::{CssStylesheet, CssStylesheetParams, CssImportNotificationFn};
+    use media::{CssMedia, CssMediaPrint};
+    use errors::CssInvalid;
+
+    // The importer's error stops the parse, so refusing all but one import
+    // shows what it was told
+    fn params() -> CssStylesheetParams {
+        let import: CssImportNotificationFn = |url, media| {
+            if url.to_str_slice() == "a.css" && media == CssMedia::single(CssMediaPrint) {
+                Ok(())
+            } else {
+                Err(CssInvalid)
+            }
+        };
+        CssStylesheetParams::new("foo").importer(import)
+    }
+    assert!(CssStylesheet::from_str(&params(), "@import url(a.css) print; p { color: red }").is_ok());
+    assert!(CssStylesheet::from_str(&params(), "@import url(a.css);").is_err());
+    assert!(CssStylesheet::from_str(&params(), "@import \"b.css\" print;").is_err());
+}
+
+#[test]
+fn test_color_resolver() {
+    use stylesheet::{CssStylesheet, CssStylesheetParams, CssColorResolutionFn};
+    use select::css_select_ctx_create;
+    use media::CssMedia;
+    use types::{CssColor, CssOriginAuthor};
+    use conversions::ToLl;
+    use errors::CssInvalid;
+    use test::fixture::color_of;
+
+    // libcss asks about names it does not know, and drops the declaration
+    // when the resolver does not know them either
+    let brand: CssColorResolutionFn = |name| {
+        if name.to_str_slice() == "brand" { Ok(CssColor::rgb(0, 128, 128)) } else { Err(CssInvalid) }
+    };
+    let params = CssStylesheetParams::new("foo").color_resolver(brand);
+    let sheet = CssStylesheet::from_str(&params, "p { color: brand } h1 { color: unknown }").unwrap();
+    let mut ctx = css_select_ctx_create();
+    ctx.append_sheet(sheet, CssOriginAuthor, CssMedia::all());
+    // The fixture's node 1 is a p and node 2 an h1
+    assert!(color_of(&ctx, 1) == Some(CssColor::rgb(0, 128, 128).to_ll()));
+    assert!(color_of(&ctx, 2).is_none());
+}
+
+#[test]
+fn test_language_level() {
+    use stylesheet::{CssStylesheet, CssStylesheetParams};
+    use types::{CssLevel21, CssLevel3};
+
+    let text = "a ~ b { opacity: 0.5; color: rgba(0, 0, 0, 0.5) }\np:nth-child(2) { margin: 1rem }";
+    let sheet = CssStylesheet::from_str(&CssStylesheetParams::new("foo").level(CssLevel21).keep_source(true), text).unwrap();
+    assert!(sheet.level() == CssLevel21);
+    let positions: ~[(uint, uint)] = sheet.validate_level().iter().map(|d| (d.line, d.column)).collect();
+    assert!(positions == ~[(1, 3), (1, 9), (1, 30), (2, 3), (2, 26)]);
+
+    let sheet = CssStylesheet::from_str(&CssStylesheetParams::new("foo").level(CssLevel3).keep_source(true), text).unwrap();
+    assert!(sheet.level() == CssLevel3);
+    assert!(sheet.validate_level().is_empty());
+    // Without the source there is nothing to check
+    let sheet = CssStylesheet::from_str(&CssStylesheetParams::new("foo").level(CssLevel21), text).unwrap();
+    assert!(sheet.validate_level().is_empty());
+}
+
+#[test]
+fn test_used_quirks() {
+    use stylesheet::{CssStylesheet, CssStylesheetParams};
+
+    let text = "p { margin: 10; color: 123456 }";
+    let quirky = CssStylesheet::from_str(&CssStylesheetParams::new("foo").quirks(true), text).unwrap();
+    assert!(quirky.used_quirks());
+    let strict = CssStylesheet::from_str(&CssStylesheetParams::new("foo"), text).unwrap();
+    assert!(!strict.used_quirks());
+}
+
+#[test]
+fn test_quirks_mode() {
+    use test::fixture::*;
+    use types::{CssLimitedQuirks, CssQuirks};
+
+    // Node 3 is a span with the class "x"
+    let mut ctx = build_ctx_with("span.X { color: #666666; }");
+    assert!(color_of(&ctx, 3).is_none());
+    ctx.set_quirks_mode(CssLimitedQuirks);
+    assert!(color_of(&ctx, 3).is_none());
+    ctx.set_quirks_mode(CssQuirks);
+    assert!(color_of(&ctx, 3).is_some());
+}
+
+#[test]
+fn test_inline_style_cache() {
+    use stylesheet::CssStylesheet;
+    use select::css_select_ctx_create;
+
+    let mut ctx = css_select_ctx_create();
+    let a = ctx.inline_style("color: red", "http://example.com/").unwrap();
+    let b = ctx.inline_style("color: red", "http://example.com/").unwrap();
+    let c = ctx.inline_style("color: blue", "http://example.com/").unwrap();
+    assert!(a.get().ll_sheet() == b.get().ll_sheet());
+    assert!(a.get().ll_sheet() != c.get().ll_sheet());
+    assert!(a.get().diagnostics().is_empty());
+
+    // With room for two, using a leaves c as the one to go
+    ctx.set_inline_style_capacity(2);
+    ctx.inline_style("color: red", "http://example.com/").unwrap();
+    ctx.inline_style("color: green", "http://example.com/").unwrap();
+    let a2 = ctx.inline_style("color: red", "http://example.com/").unwrap();
+    let c2 = ctx.inline_style("color: blue", "http://example.com/").unwrap();
+    assert!(a2.get().ll_sheet() == a.get().ll_sheet());
+    assert!(c2.get().ll_sheet() != c.get().ll_sheet());
+
+    let bad = CssStylesheet::from_style_attribute("color red; margin: 0", "http://example.com/").unwrap();
+    assert!(bad.diagnostics().len() == 1);
+}
+
+#[test]
+fn test_counting_allocator() {
+    use alloc::{CssAllocator, CssAllocatorHandle, CssCountingAllocator};
+    use stylesheet::{CssStylesheet, CssStylesheetParams};
+    use select::{css_select_ctx_create_with, CssPseudoElementNone};
+    use computed::{compose_owned, FontSizeComputer};
+    use cascade::CssDefaultFontSizeComputer;
+    use types::CssOriginAuthor;
+    use media::CssMedia;
+    use test::fixture::{TreeNode, TreeHandler};
+
+    let counter = CssAllocatorHandle::new(~CssCountingAllocator::new() as ~CssAllocator:Send+Freeze);
+    assert!(counter.bytes_allocated() == Some(0));
+    {
+        let params = CssStylesheetParams::new("foo").allocator(counter.clone());
+        let sheet = CssStylesheet::from_str(&params, "p { color: red }").unwrap();
+        let for_sheet = counter.bytes_allocated().unwrap();
+        assert!(for_sheet > 0);
+        // What the sheet counts went through the counter, with some overhead
+        let sheet_usage = sheet.memory_usage();
+        assert!(sheet_usage > 0 && sheet_usage < for_sheet);
+
+        let mut ctx = css_select_ctx_create_with(counter.clone());
+        ctx.append_sheet(sheet, CssOriginAuthor, CssMedia::all());
+        let inline = ctx.inline_style("color: blue", "http://example.com/").unwrap();
+        assert!(counter.bytes_allocated().unwrap() > for_sheet);
+        // The context's own memory, its selection hash, is counted too
+        let ctx_usage = ctx.memory_usage();
+        assert!(ctx_usage > sheet_usage + inline.get().memory_usage());
+
+        // Node 1 has a class, whose array libcss frees through the context
+        let results = ctx.select_style(&TreeNode { index: 1 }, CssMedia::all(), None, &TreeHandler);
+        assert!(results.memory_usage() > 0);
+        assert!(ctx.memory_usage() == ctx_usage + results.memory_usage());
+
+        // Owned styles are allocated like the results they came from
+        let before = counter.bytes_allocated().unwrap();
+        let owned = results.owned_computed_style(CssPseudoElementNone);
+        assert!(counter.bytes_allocated().unwrap() > before);
+        let before = counter.bytes_allocated().unwrap();
+        let composed = compose_owned(&owned.style(), &results.computed_style(CssPseudoElementNone),
+                                     @CssDefaultFontSizeComputer::new() as @FontSizeComputer);
+        assert!(counter.bytes_allocated().unwrap() > before);
+        assert!(composed.style().color() == owned.style().color());
+    }
+    // Everything went back through the allocator
+    assert!(counter.bytes_allocated() == Some(0));
+
+    let capped = CssAllocatorHandle::new(~CssCountingAllocator::with_limit(16) as ~CssAllocator:Send+Freeze);
+    let params = CssStylesheetParams::new("foo").allocator(capped.clone());
+    assert!(CssStylesheet::from_str(&params, "p { color: red }").is_err());
+    assert!(capped.bytes_allocated() == Some(0));
+}
+
+#[test]
+fn test_sheet_list() {
+    use extra::arc::Arc;
+    use stylesheet::{CssStylesheet, CssStylesheetParams, CssSharedStylesheet};
+    use select::{CssSelectCtx, css_select_ctx_create};
+    use types::CssOriginAuthor;
+    use media::CssMedia;
+    use test::fixture::color_of;
+
+    fn sheet(text: &str) -> CssSharedStylesheet {
+        Arc::new(CssStylesheet::from_str(&CssStylesheetParams::new("foo"), text).unwrap())
+    }
+    // get_sheet checks that libcss's list agrees with the context's
+    fn check(ctx: &CssSelectCtx, sheets: &[&CssSharedStylesheet]) {
+        assert!(ctx.count_sheets() == sheets.len());
+        for (i, sheet) in sheets.iter().enumerate() {
+            assert!(ctx.get_sheet(i).ll_sheet() == sheet.get().ll_sheet());
+        }
+    }
+
+    // The fixture's node 1 is a p, and the last sheet to set its color wins
+    let a = sheet("p { color: #111111 }");
+    let b = sheet("p { color: #222222 }");
+    let c = sheet("p { color: #333333 }");
+    let mut ctx = css_select_ctx_create();
+    ctx.append_shared_sheet(a.clone(), CssOriginAuthor, CssMedia::all());
+    ctx.append_shared_sheet(a.clone(), CssOriginAuthor, CssMedia::all());
+    ctx.insert_sheet(1, b.clone(), CssOriginAuthor, CssMedia::all());
+    check(&ctx, [&a, &b, &a]);
+    let a_color = color_of(&ctx, 1);
+
+    // Removing the second place a has must leave the first
+    let removed = ctx.remove_sheet(2);
+    assert!(removed.get().ll_sheet() == a.get().ll_sheet());
+    check(&ctx, [&a, &b]);
+    let b_color = color_of(&ctx, 1);
+    assert!(b_color != a_color);
+
+    let old = ctx.replace_sheet(0, c.clone());
+    assert!(old.get().ll_sheet() == a.get().ll_sheet());
+    check(&ctx, [&c, &b]);
+    assert!(color_of(&ctx, 1) == b_color);
+    ctx.replace_sheet(1, c.clone());
+    check(&ctx, [&c, &c]);
+    ctx.remove_sheet(0);
+    check(&ctx, [&c]);
+    assert!(color_of(&ctx, 1) != b_color);
+}
+
+#[test]
+fn test_rule_mutation() {
+    use stylesheet::{CssStylesheet, CssStylesheetParams, css_stylesheet_create};
+    use serializer::CssSerializeMinified;
+    use errors::{CssBadParm, CssInvalid};
+
+    // Nothing can change without inspection
+    let params = CssStylesheetParams::for_style_attribute("http://example.com/").inspect(false);
+    let mut style = CssStylesheet::from_str(&params, "color: red; margin: 0").unwrap();
+    assert!(style.rules().is_empty() && style.diagnostics().is_empty());
+    assert!(match style.set_property(0, "color", "blue", true) { Err(CssBadParm) => true, _ => false });
+
+    let mut style = CssStylesheet::from_style_attribute("color: red; margin: 0", "http://example.com/").unwrap();
+    assert!(style.rules()[0].declarations().unwrap().len() == 2);
+    style.set_property(0, "color", "blue", true).unwrap();
+    assert!(style.to_css(CssSerializeMinified) == ~"color:blue!important;margin:0");
+    assert!(style.remove_property(0, "MARGIN").unwrap());
+    assert!(!style.remove_property(0, "margin").unwrap());
+    assert!(style.to_css(CssSerializeMinified) == ~"color:blue!important");
+    assert!(match style.set_property(0, "color", "red; margin: 0", false) { Err(CssInvalid) => true, _ => false });
+    // Values are checked by libcss, at the sheet's level and quirks
+    assert!(match style.set_property(0, "color", "12px", false) { Err(CssInvalid) => true, _ => false });
+    assert!(match style.set_property(0, "margin", "10", false) { Err(CssInvalid) => true, _ => false });
+    assert!(match style.delete_rule(0) { Err(CssBadParm) => true, _ => false });
+
+    let text = "@import \"a.css\";\n@media print { p { color: red; colour: blue } }";
+    let mut sheet = CssStylesheet::from_str(&CssStylesheetParams::new("foo"), text).unwrap();
+    let diagnostics = sheet.diagnostics().len();
+    assert!(match sheet.insert_rule("@import \"b.css\";", 2) { Err(CssInvalid) => true, _ => false });
+    sheet.insert_rule("@import \"b.css\";", 1).unwrap();
+    sheet.insert_rule("h1 { margin: 0 }", 3).unwrap();
+    // Rule 3 in iter_rules order is the p inside @media
+    sheet.set_property(3, "margin", "1em", false).unwrap();
+    assert!(sheet.to_css(CssSerializeMinified) ==
+            ~"@import url(\"a.css\");@import url(\"b.css\");@media print{p{color:red;margin:1em}}h1{margin:0}");
+    sheet.delete_rule(0).unwrap();
+    assert!(sheet.rules().len() == 3);
+    // The diagnostics are those of the text the sheet was rebuilt from
+    assert!(diagnostics == 1 && sheet.diagnostics().is_empty());
+    assert!(match sheet.delete_rule(3) { Err(CssBadParm) => true, _ => false });
+
+    // And so are the charset and the source, which are now UTF-8
+    let params = CssStylesheetParams::new("foo").charset("ISO-8859-1").keep_source(true);
+    let mut latin1 = css_stylesheet_create(&params).unwrap();
+    let mut data = "p { content: \"caf".as_bytes().to_owned();
+    data.push(0xE9);
+    data.push_all("\" }".as_bytes());
+    latin1.append_data(data);
+    latin1.data_done().unwrap();
+    assert!(latin1.charset().unwrap().name == ~"ISO-8859-1");
+    latin1.insert_rule("h1 { margin: 0 }", 1).unwrap();
+    let minified = ~"p{content:\"caf\u00e9\"}h1{margin:0}";
+    assert!(latin1.charset().unwrap().name == ~"UTF-8");
+    assert!(latin1.to_css(CssSerializeMinified) == minified);
+    assert!(latin1.bytes_consumed() == minified.len());
+
+    // @namespace and unknown at-rules survive a rebuild, and inserted rules
+    // can use the sheet's prefixes
+    let text = "@import \"a.css\"; @namespace x \"http://x\"; x|p { color: red } @unknown foo { bar }";
+    let mut sheet = CssStylesheet::from_str(&CssStylesheetParams::new("foo"), text).unwrap();
+    assert!(match sheet.insert_rule("@namespace y \"http://y\";", 3) { Err(CssInvalid) => true, _ => false });
+    assert!(match sheet.insert_rule("@import \"b.css\";", 2) { Err(CssInvalid) => true, _ => false });
+    assert!(match sheet.insert_rule("z|p { margin: 0 }", 3) { Err(CssInvalid) => true, _ => false });
+    sheet.insert_rule("@namespace y \"http://y\";", 2).unwrap();
+    sheet.insert_rule("y|h1, x|span { margin: 0 }", 4).unwrap();
+    assert!(sheet.to_css(CssSerializeMinified) ==
+            ~"@import url(\"a.css\");@namespace x url(\"http://x\");@namespace y url(\"http://y\");\
+              x|p{color:red}y|h1,x|span{margin:0}@unknown foo{bar}");
+    assert!(sheet.diagnostics().len() == 1);
+    assert!(sheet.diagnostics()[0].message == ~"Unknown at-rule @unknown");
+
+    // Descriptors are checked as libcss checks them in @font-face
+    let text = "@font-face { font-family: x } @page { margin: 0 }";
+    let mut sheet = CssStylesheet::from_str(&CssStylesheetParams::new("foo"), text).unwrap();
+    sheet.set_property(0, "src", "url(x.ttf) format(\"truetype\"), local(x)", false).unwrap();
+    assert!(match sheet.set_property(0, "font-weight", "heavy", false) { Err(CssInvalid) => true, _ => false });
+    assert!(match sheet.set_property(0, "color", "red", false) { Err(CssInvalid) => true, _ => false });
+    sheet.set_property(1, "margin-top", "1in", false).unwrap();
+    assert!(match sheet.set_property(1, "margin-top", "red", false) { Err(CssInvalid) => true, _ => false });
+    assert!(sheet.to_css(CssSerializeMinified) ==
+            ~"@font-face{font-family:x;src:url(\"x.ttf\") format(\"truetype\"),local(x)}@page{margin:0;margin-top:1in}");
+    assert!(sheet.diagnostics().is_empty());
+}
+
+#[test]
+fn test_mutation_is_seen_by_selection() {
+    use test::fixture::*;
+    use select::css_select_ctx_create;
+    use types::CssOriginAuthor;
+    use media::CssMedia;
+    use errors::CssInvalid;
+
+    // Node 1 is a p and node 2 an h1
+    let mut ctx = build_ctx_with("h1 { color: #444444; }");
+    let shared = ctx.get_shared_sheet(0);
+    let mut other = css_select_ctx_create();
+    other.append_shared_sheet(shared.clone(), CssOriginAuthor, CssMedia::all());
+    assert!(color_of(&ctx, 1).is_none());
+
+    ctx.update_sheet(0, |sheet| sheet.insert_rule("p { color: #222222 }", 1)).unwrap();
+    assert!(color_of(&ctx, 1).is_some());
+    ctx.update_sheet(0, |sheet| sheet.delete_rule(0)).unwrap();
+    assert!(color_of(&ctx, 2).is_none());
+    assert!(color_of(&ctx, 1).is_some());
+
+    let invalid = ctx.update_sheet(0, |sheet| sheet.insert_rule("not a rule", 0));
+    assert!(match invalid { Err(CssInvalid) => true, _ => false });
+    assert!(ctx.get_sheet(0).rules().len() == 1);
+    // Contexts sharing the old sheet are unaffected until given the new one
+    assert!(shared.get().rules().len() == 1);
+    assert!(color_of(&ctx, 2).is_none());
+    assert!(color_of(&other, 1).is_none());
+    assert!(color_of(&other, 2).is_some());
+    other.replace_sheet(0, ctx.get_shared_sheet(0));
+    assert!(color_of(&other, 1).is_some());
+    assert!(color_of(&other, 2).is_none());
+}
+
+#[test]
+fn test_media_queries() {
+    use media::*;
+
+    assert!(CssMedia::parse("screen, print") ==
+            Some(CssMedia::single(CssMediaScreen).union(&CssMedia::single(CssMediaPrint))));
+    assert!(CssMedia::parse("screen print").is_none());
+
+    let env = MediaEnvironment {
+        media_type: CssMediaScreen,
+        width: 1024.0,
+        height: 768.0,
+        resolution: 96.0,
+        color: 8
     };
 
-    let sheet: CssStylesheet = css_stylesheet_create(&params);
-    let _arc = Arc::new(sheet);
+    assert!(MediaQueryList::parse("").evaluate(&env));
+    assert!(MediaQueryList::parse("screen and (min-width: 800px)").evaluate(&env));
+    assert!(!MediaQueryList::parse("screen and (max-width: 50em)").evaluate(&env));
+    assert!(MediaQueryList::parse("(orientation: landscape) and (color)").evaluate(&env));
+    assert!(MediaQueryList::parse("(aspect-ratio: 4/3)").evaluate(&env));
+    assert!(!MediaQueryList::parse("(min-resolution: 2dppx)").evaluate(&env));
+    assert!(MediaQueryList::parse("print, not print").evaluate(&env));
+    // Malformed queries never match
+    assert!(!MediaQueryList::parse("screen and min-width: 800px").evaluate(&env));
+    // Non-ASCII text is not whitespace, even where its bytes look like it
+    assert!(!MediaQueryList::parse("\u00e0").evaluate(&env));
+    assert!(!MediaQueryList::parse("screen\u00a0and (color)").evaluate(&env));
+    assert!(!MediaQueryList::parse("(\u0105: 1) and \u0085screen").evaluate(&env));
+    assert!(MediaQueryList::parse("screen and (min-width: 800px)").to_media(&env) ==
+            CssMedia::single(CssMediaScreen));
+}
+
+#[test]
+fn test_media_environment_in_selection() {
+    use test::fixture::*;
+    use stylesheet::{CssStylesheet, CssStylesheetParams};
+    use select::css_select_ctx_create;
+    use types::CssOriginAuthor;
+    use serializer::CssSerializeMinified;
+    use media::*;
+
+    fn environment(width: f64) -> MediaEnvironment {
+        MediaEnvironment {
+            media_type: CssMediaScreen,
+            width: width,
+            height: 600.0,
+            resolution: 96.0,
+            color: 8
+        }
+    }
+
+    // Node 1 is a p and node 2 an h1
+    let text = "p { color: #111111 }\
+                @media screen and (min-width: 500px) { p { color: #222222 } }\
+                @media (max-width: 400px) { h1 { color: #333333 } }";
+    let sheet = CssStylesheet::from_str(&CssStylesheetParams::new("foo"), text).unwrap();
+    let evaluated = sheet.evaluate_media(&environment(800.0)).unwrap();
+    assert!(evaluated.to_css(CssSerializeMinified) ==
+            ~"p{color:#111111}@media screen{p{color:#222222}}");
+    let evaluated = sheet.evaluate_media(&environment(300.0)).unwrap();
+    assert!(evaluated.to_css(CssSerializeMinified) == ~"p{color:#111111}@media all{h1{color:#333333}}");
+
+    let mut ctx = css_select_ctx_create();
+    ctx.set_media_environment(Some(environment(800.0)));
+    ctx.append_sheet(sheet, CssOriginAuthor, CssMedia::all());
+    assert!(color_of(&ctx, 1) == Some(0xff222222));
+    assert!(color_of(&ctx, 2).is_none());
+
+    // Narrowing the viewport evaluates the sheets again
+    ctx.set_media_environment(Some(environment(300.0)));
+    assert!(color_of(&ctx, 1) == Some(0xff111111));
+    assert!(color_of(&ctx, 2) == Some(0xff333333));
+    // The context hands back the sheet as it was appended
+    assert!(ctx.get_sheet(0).to_css(CssSerializeMinified).contains("min-width"));
+    assert!(ctx.count_sheets() == 1);
+}
+
+#[test]
+fn test_charset_detection() {
+    use stylesheet::*;
+
+    let sheet = bytes!("@charset \"ISO-8859-1\"; p { color: red }");
+    assert!(detect_charset(None, sheet, Some("UTF-16")) ==
+            CssCharset { name: ~"ISO-8859-1", source: CssCharsetRule });
+    assert!(detect_charset(Some("KOI8-R"), sheet, None).source == CssCharsetTransport);
+    assert!(detect_charset(None, &[0xFFu8, 0xFE, 0x00, 0x00], None).name == ~"UTF-32LE");
+    assert!(detect_charset(None, &[0xFFu8, 0xFE, 0x40, 0x00], None).name == ~"UTF-16LE");
+    // Anything but the exact form of the rule is ignored
+    assert!(detect_charset(None, bytes!("@charset 'ISO-8859-1';"), Some("Shift_JIS")) ==
+            CssCharset { name: ~"Shift_JIS", source: CssCharsetReferrer });
+    assert!(detect_charset(None, bytes!("p {}"), None).source == CssCharsetDefault);
+}
+
+#[test]
+fn test_from_reader() {
+    use stylesheet::*;
+    use serializer::CssSerializeMinified;
+    use errors::CssFileNotFound;
+    use std::io::{Reader, SeekStyle, with_bytes_reader};
+    use std::path::Path;
+
+    // Hands out one byte per read, so nothing arrives in one piece
+    struct TrickleReader {
+        data: ~[u8],
+        pos: @mut uint
+    }
+
+    impl Reader for TrickleReader {
+        fn read(&self, bytes: &mut [u8], len: uint) -> uint {
+            if len == 0 || self.eof() {
+                return 0;
+            }
+            bytes[0] = self.data[*self.pos];
+            *self.pos += 1;
+            1
+        }
+        fn read_byte(&self) -> int {
+            if self.eof() {
+                return -1;
+            }
+            *self.pos += 1;
+            self.data[*self.pos - 1] as int
+        }
+        fn eof(&self) -> bool { *self.pos == self.data.len() }
+        fn seek(&self, _position: int, _style: SeekStyle) { fail!(~"seeking a trickle") }
+        fn tell(&self) -> uint { *self.pos }
+    }
+
+    fn trickle(data: ~[u8]) -> @Reader {
+        @TrickleReader { data: data, pos: @mut 0 } as @Reader
+    }
+
+    let params = CssStylesheetParams::new("foo");
+    let mut latin1 = "@charset \"ISO-8859-1\"; p { content: \"caf".as_bytes().to_owned();
+    latin1.push(0xE9);
+    latin1.push_all("\" }".as_bytes());
+    let sheet = CssStylesheet::from_reader(&params, trickle(latin1.clone())).unwrap();
+    assert!(sheet.charset() == Some(CssCharset { name: ~"ISO-8859-1", source: CssCharsetRule }));
+    assert!(sheet.to_css(CssSerializeMinified) == ~"@charset \"ISO-8859-1\";p{content:\"caf\u00e9\"}");
+    assert!(sheet.bytes_consumed() == latin1.len());
+
+    let mut bom = ~[0xEFu8, 0xBB, 0xBF];
+    bom.push_all("p { content: \"caf\u00e9\" }".as_bytes());
+    let referred = params.clone().referrer_charset("ISO-8859-1");
+    let sheet = CssStylesheet::from_reader(&referred, trickle(bom.clone())).unwrap();
+    assert!(sheet.charset() == Some(CssCharset { name: ~"UTF-8", source: CssCharsetBom }));
+    assert!(sheet.to_css(CssSerializeMinified) == ~"p{content:\"caf\u00e9\"}");
+    assert!(sheet.bytes_consumed() == bom.len());
+
+    // Too short to hold a BOM or @charset, so the referrer decides
+    let sheet = CssStylesheet::from_reader(&referred, trickle("a{}".as_bytes().to_owned())).unwrap();
+    assert!(sheet.charset() == Some(CssCharset { name: ~"ISO-8859-1", source: CssCharsetReferrer }));
+    assert!(sheet.rules().len() == 1);
+
+    // Many chunks of CHUNK_SIZE bytes
+    let mut text = ~"";
+    for i in range(0, 1000) {
+        text.push_str(fmt!("p.c%d { margin: %dpx }\n", i, i));
+    }
+    let sheet = do with_bytes_reader(text.as_bytes()) |reader| {
+        CssStylesheet::from_reader(&params, reader).unwrap()
+    };
+    assert!(sheet.bytes_consumed() == text.len());
+    assert!(sheet.rules().len() == 1000);
+    assert!(sheet.diagnostics().is_empty());
+    let whole = CssStylesheet::from_str(&params, text).unwrap();
+    assert!(sheet.to_css(CssSerializeMinified) == whole.to_css(CssSerializeMinified));
+
+    let missing = CssStylesheet::from_file(&params, &Path("/nonexistent/missing.css"));
+    assert!(match missing { Err(CssFileNotFound) => true, _ => false });
+}
+
+#[test]
+fn test_str_is_utf8() {
+    use stylesheet::*;
+    use serializer::CssSerializeMinified;
+
+    // The text was decoded before it got here, so the rule must not
+    // decode it a second time
+    let text = "@charset \"ISO-8859-1\"; p { content: \"caf\u00e9\" }";
+    let sheet = CssStylesheet::from_str(&CssStylesheetParams::new("foo"), text).unwrap();
+    assert!(sheet.charset().unwrap().name == ~"UTF-8");
+    let css = sheet.to_css(CssSerializeMinified);
+    assert!(css.contains("caf\u00e9"));
+    assert!(!css.contains("caf\u00c3"));
+}
+
+#[test]
+fn test_diagnostics() {
+    use diagnostics::*;
+    use stylesheet::{CssStylesheet, CssStylesheetParams, css_stylesheet_create};
+
+    let text = ~"p { color: red; colour: blue }\n" +
+               "h1 { margin 0; padding: 1px !imp }\n" +
+               "#1a { color: red }\n" +
+               "@import url(late.css);\n" +
+               "a { content: \"unclosed\n }";
+    let diagnostics = check_stylesheet(text);
+    let summary: ~[(uint, uint, CssSeverity, CssDropped)] = diagnostics.iter().map(|d| {
+        (d.line, d.column, d.severity, d.dropped.clone())
+    }).collect();
+    assert!(summary == ~[
+        (1, 17, CssSeverityWarning, CssDroppedDeclaration(~"colour")),
+        (2, 13, CssSeverityError, CssDroppedDeclaration(~"margin")),
+        (2, 29, CssSeverityError, CssDroppedDeclaration(~"padding")),
+        (3, 1, CssSeverityError, CssDroppedRule(~"#1a")),
+        (4, 1, CssSeverityError, CssDroppedRule(~"@import url(\"late.css\")")),
+        (5, 14, CssSeverityError, CssDroppedDeclaration(~"content"))
+    ]);
+    assert!(diagnostics[1].token == ~"0");
+    assert!(check_stylesheet("@media print { p { color: red } }").is_empty());
+
+    // A stray '}' in a style attribute is reported and skipped
+    assert!(check_declarations("color: red }").len() == 1);
+    assert!(check_declarations("}").len() == 1);
+    assert!(check_declarations("a } color: red").len() == 2);
+
+    // A sheet collects them as its data arrives, a rule at a time, along
+    // with the declarations libcss drops for their values
+    let params = CssStylesheetParams::new("foo").charset("UTF-8");
+    let mut sheet = css_stylesheet_create(&params).unwrap();
+    sheet.append_data("p { color: 12px; margin: 0 }\nh1 { colour".as_bytes());
+    assert!(sheet.diagnostics().len() == 1);
+    assert!((sheet.diagnostics()[0].line, sheet.diagnostics()[0].column) == (1, 12));
+    assert!(sheet.diagnostics()[0].token == ~"12px");
+    assert!(sheet.diagnostics()[0].dropped == CssDroppedDeclaration(~"color"));
+    sheet.append_data(": red }".as_bytes());
+    assert!(sheet.diagnostics().len() == 2);
+    assert!((sheet.diagnostics()[1].line, sheet.diagnostics()[1].column) == (2, 6));
+    sheet.data_done().unwrap();
+    assert!(sheet.diagnostics().len() == 2);
+
+    // However the data is split up
+    let text = ~"p { color: 12px }\n@page { margin: red; margin: 1cm }\n" +
+               "@font-face { src: url(a.ttf) format(\"truetype\"); font-weight: heavy }";
+    let whole = CssStylesheet::from_str(&params, text).unwrap();
+    let summary: ~[(uint, uint, CssDropped)] = whole.diagnostics().iter().map(|d| {
+        (d.line, d.column, d.dropped.clone())
+    }).collect();
+    assert!(summary == ~[
+        (1, 12, CssDroppedDeclaration(~"color")),
+        (2, 17, CssDroppedDeclaration(~"margin")),
+        (3, 63, CssDroppedDeclaration(~"font-weight"))
+    ]);
+    let mut pieces = css_stylesheet_create(&params).unwrap();
+    for byte in text.as_bytes().iter() {
+        pieces.append_data([*byte]);
+    }
+    pieces.data_done().unwrap();
+    assert!(pieces.diagnostics() == whole.diagnostics());
+    assert!(pieces.rules() == whole.rules());
+}
+
+#[test]
+fn test_rules() {
+    use stylesheet::{CssStylesheet, CssStylesheetParams};
+    use rules::*;
+
+    let text = ~"@charset \"UTF-8\";\n" +
+               "@import \"a.css\" screen;\n" +
+               "#a, .b.c, div p { COLOR: red !important; colour: blue; margin: red }\n" +
+               "@media print { p:first-line { margin: 0 } }\n" +
+               "@page :first { margin: 1in }\n" +
+               "@font-face { font-family: x; src: url(x.ttf); font-weight: heavy }";
+    let sheet = CssStylesheet::from_str(&CssStylesheetParams::new("foo"), text).unwrap();
+    let rules = sheet.rules();
+    assert!(rules.len() == 6);
+    match rules[0] {
+        CssRuleCharset(ref rule) => assert!(rule.encoding == ~"UTF-8"),
+        _ => fail!()
+    }
+    match rules[1] {
+        CssRuleImport(ref rule) => {
+            assert!(rule.url == ~"a.css");
+            assert!(rule.media == ~"screen");
+        }
+        _ => fail!()
+    }
+    match rules[2] {
+        CssRuleStyle(ref rule) => {
+            let texts: ~[~str] = rule.selectors.iter().map(|s| s.text.clone()).collect();
+            assert!(texts == ~[~"#a", ~".b.c", ~"div p"]);
+            assert!(rule.selectors[0].specificity > rule.selectors[1].specificity);
+            assert!(rule.selectors[1].specificity > rule.selectors[2].specificity);
+            // The misspelt property and the value libcss rejects were dropped
+            assert!(rule.declarations == ~[CssDeclaration {
+                property: ~"color",
+                value: ~"red",
+                important: true
+            }]);
+        }
+        _ => fail!()
+    }
+    match rules[4] {
+        CssRulePage(ref rule) => assert!(rule.selector == ~":first"),
+        _ => fail!()
+    }
+    assert!(rules[5].declarations().unwrap().len() == 2);
+
+    let style_rules: ~[&CssStyleRule] = sheet.style_rules().collect();
+    assert!(style_rules.len() == 2);
+    assert!(style_rules[1].selectors[0].text == ~"p:first-line");
+    assert!(style_rules[1].selectors[0].specificity == CssSpecificity { ids: 0, classes: 0, elements: 2 });
+    let all_rules: ~[&CssRule] = sheet.iter_rules().collect();
+    assert!(all_rules.len() == 7);
+}
+
+#[test]
+fn test_length_resolution() {
+    use fixed::CssFixed;
+    use length::LengthContext;
+    use types::*;
+    use values::{CssMarginAuto, CssMarginSet, CssBorderWidthThin, CssLineHeightNumber};
+    use values::{CssBorderWidthMedium, CssBorderWidthThick};
+
+    let px = |n: i32| CssFixed::from_int(n);
+    // A 20px font in a 300px wide box
+    let ctx = LengthContext::new(px(20), px(300));
+    assert!(CssUnitPx(CssFixed::from_f64(1.5)).to_px(&ctx) == Some(CssFixed::from_f64(1.5)));
+    assert!(CssUnitEm(CssFixed::from_f64(1.5)).to_px(&ctx) == Some(px(30)));
+    assert!(CssUnitEx(px(1)).to_px(&ctx) == Some(px(10)));
+    assert!(CssUnitPct(px(50)).to_px(&ctx) == Some(px(150)));
+    assert!(CssUnitIn(px(1)).to_px(&ctx) == Some(px(96)));
+    assert!(CssUnitPt(px(72)).to_px(&ctx) == Some(px(96)));
+    assert!(CssUnitPc(px(1)).to_px(&ctx) == Some(px(16)));
+    assert!(CssUnitCm(px(254)).to_px(&ctx) == Some(px(9600)));
+    // 1mm is 3.7795px, 3870/1024 to the nearest
+    assert!(CssUnitMm(px(1)).to_px(&ctx) == Some(CssFixed::from_raw(3870)));
+    assert!(CssUnitMm(px(-1)).to_px(&ctx) == Some(CssFixed::from_raw(-3870)));
+    assert!(CssUnitDeg(px(1)).to_px(&ctx).is_none());
+    assert!(CssUnitIn(px(1)).to_px(&ctx.dpi(px(72))) == Some(px(72)));
+
+    assert!(CssMarginSet(CssUnitEm(px(1))).to_px(&ctx) == Some(px(20)));
+    assert!(CssMarginAuto.to_px(&ctx).is_none());
+    assert!(CssBorderWidthThin.to_px(&ctx) == Some(px(1)));
+    assert!(CssBorderWidthMedium.to_px(&ctx) == Some(px(2)));
+    assert!(CssBorderWidthThick.to_px(&ctx) == Some(px(4)));
+    assert!(CssLineHeightNumber(CssFixed::from_f64(1.5)).to_px(&ctx) == Some(px(30)));
+}
+
+#[test]
+fn test_fixed_arithmetic() {
+    use fixed::CssFixed;
+    use std::i32;
+
+    let half = CssFixed::from_f64(0.5);
+    let three = CssFixed::from_int(3);
+    assert!(three.to_raw() == 3 * 1024);
+    assert!((three + half).to_f64() == 3.5);
+    assert!((half - three).to_f64() == -2.5);
+    assert!((three * half).to_f64() == 1.5);
+    assert!((three / CssFixed::from_int(2)).to_f64() == 1.5);
+    // A third is 341.33/1024, two thirds 682.67/1024
+    assert!((CssFixed::from_int(1) / three).to_raw() == 341);
+    assert!((CssFixed::from_int(2) / three).to_raw() == 683);
+    assert!(CssFixed::from_f64(-1.5).to_int() == -2);
+    assert!(-half < half);
+
+    // Out of range values saturate instead of wrapping
+    let max = CssFixed::from_raw(i32::max_value);
+    let min = CssFixed::from_raw(i32::min_value);
+    assert!(CssFixed::from_int(3000000) == max);
+    assert!(CssFixed::from_int(-3000000) == min);
+    assert!(CssFixed::from_f64(1e12) == max);
+    assert!(CssFixed::from_f64(0.0 / 0.0) == CssFixed::from_int(0));
+    assert!(max + three == max);
+    assert!(min - three == min);
+    assert!(max * three == max);
+    assert!(three / CssFixed::from_int(0) == max);
+    assert!(-min == max);
+
+    assert!(three.to_str() == ~"3");
+    assert!((-half).to_str() == ~"-0.5");
+    assert!(CssFixed::from_raw(1).to_str() == ~"0.001");
+    assert!(CssFixed::from_f64(12.25).to_str() == ~"12.25");
+}
+
+#[test]
+fn test_color() {
+    use types::CssColor;
+    use conversions::{ToLl, ll_color_to_hl_color};
+
+    let teal = CssColor::rgb(0, 128, 128);
+    assert!(CssColor::parse("teal").unwrap() == teal);
+    assert!(CssColor::parse("TEAL").unwrap() == teal);
+    assert!(CssColor::parse("#008080").unwrap() == teal);
+    assert!(CssColor::parse("#0f08").unwrap() == CssColor::rgba(0, 255, 0, 136));
+    assert!(CssColor::parse("transparent").unwrap() == CssColor::rgba(0, 0, 0, 0));
+    assert!(CssColor::parse("#12345").is_err());
+    assert!(CssColor::parse("#ggg").is_err());
+    assert!(CssColor::parse("tealish").is_err());
+
+    assert!(CssColor::hsl(180.0, 1.0, 0.5) == CssColor::rgb(0, 255, 255));
+    assert!(CssColor::hsl(-120.0, 1.0, 0.5) == CssColor::rgb(0, 0, 255));
+    assert!(CssColor::hsla(0.0, 0.0, 1.0, 0) == CssColor::rgba(255, 255, 255, 0));
+
+    let white = CssColor::rgb(255, 255, 255);
+    let half_black = CssColor::rgba(0, 0, 0, 128);
+    assert!(half_black.over(&white) == CssColor::rgb(127, 127, 127));
+    assert!(teal.over(&half_black) == teal);
+    assert!(half_black.over(&half_black) == CssColor::rgba(0, 0, 0, 192));
+    assert!(CssColor::rgba(255, 0, 0, 0).over(&CssColor::rgba(0, 0, 0, 0)).a == 0);
+
+    assert!(teal.to_css() == ~"#008080");
+    assert!(half_black.to_css() == ~"rgba(0, 0, 0, 0.502)");
+
+    // css_color is ARGB whatever the byte order
+    assert!(teal.to_ll() == 0xff008080);
+    assert!(ll_color_to_hl_color(0x80ff0000) == CssColor::rgba(255, 0, 0, 128));
+}
+
+#[test]
+fn test_contrast() {
+    use test::fixture::*;
+    use contrast::check_contrast;
+    use types::CssColor;
+    use select::CssPseudoElementNone;
+    use media::CssMedia;
+    use values::{CssFontWeightNormal, CssFontWeightBold};
+    use fixed::CssFixed;
+
+    // Node 1 is a p with no background, under the root div
+    let ctx = build_ctx_with("div { background-color: #000000; } p { color: #777777; }");
+    let div = ctx.select_style(&TreeNode { index: 0 }, CssMedia::all(), None, &TreeHandler);
+    let p = ctx.select_style(&TreeNode { index: 1 }, CssMedia::all(), None, &TreeHandler);
+    let ancestors = [div.computed_style(CssPseudoElementNone)];
+    let white = CssColor::rgb(255, 255, 255);
+
+    let report = check_contrast(&p.computed_style(CssPseudoElementNone), ancestors, &white,
+                                CssFixed::from_int(16), CssFontWeightNormal);
+    assert!(report.background == CssColor::rgb(0, 0, 0));
+    assert!(report.ratio > 4.6 && report.ratio < 4.7);
+    assert!(report.aa && !report.aaa && !report.large_text);
+
+    let bold = check_contrast(&p.computed_style(CssPseudoElementNone), ancestors, &white,
+                              CssFixed::from_int(19), CssFontWeightBold);
+    assert!(bold.large_text && bold.aaa);
+
+    // Without the div the p is on the canvas
+    let alone = check_contrast(&p.computed_style(CssPseudoElementNone), [], &white,
+                               CssFixed::from_int(16), CssFontWeightNormal);
+    assert!(alone.background == white);
+    assert!(!alone.aa);
+    assert!(white.contrast_ratio(&CssColor::rgb(0, 0, 0)) == 21.0);
+}
+
+#[test]
+fn test_serializer_round_trip() {
+    use stylesheet::{CssStylesheet, CssStylesheetParams};
+    use serializer::*;
+    use diagnostics::CssSeverityWarning;
+    use select::css_select_ctx_create;
+    use types::CssOriginAuthor;
+    use media::CssMedia;
+    use test::fixture::*;
+
+    // The fixture's resolver fails, and these sheets have URLs
+    fn colors(sheet: CssStylesheet) -> ~[Option<u32>] {
+        let mut ctx = css_select_ctx_create();
+        ctx.append_sheet(sheet, CssOriginAuthor, CssMedia::all());
+        range(0u, 64).map(|index| color_of(&ctx, index)).collect()
+    }
+
+    let corpus = [
+        "h1 { color: red; }",
+        "@charset \"UTF-8\";\n@import url(a.css) screen , print;\np{margin:0 AUTO; color: #00F}",
+        "/* comment */ div  >  p + span ~ h1, #id.cls[href ^= 'x'], [title $= '2 item'] { font: 12px / 1.5 'Times New Roman', serif !important; color: olive }",
+        "@media screen and ( min-width : 100px ) { span:last-child, div p:first-child { color: rgb( 0 , 0 , 255 ) } }",
+        "@page :first { margin: 1in } @font-face { font-family: x; src: url(x.ttf) } p { color: teal }",
+        "p:not(.a) { content: \"say \\\"hi\\\"\"; colour: blue; color: maroon } bad{ { } em { }",
+        "@namespace url(http://www.w3.org/1999/xhtml); @namespace x \"http://x\"; p { color: green }\
+         x|span, *|h1 { color: blue } @unknown foo { bar { baz: 1 } } div { color: red }",
+        "@foo bar; span[title] { color: navy } @media all { @bar; [lang|=en] { color: lime } }"
+    ];
+    for text in corpus.iter() {
+        let params = CssStylesheetParams::new("foo");
+        let sheet = CssStylesheet::from_str(&params, *text).unwrap();
+        let pretty = sheet.to_css(CssSerializePretty);
+        let minified = sheet.to_css(CssSerializeMinified);
+        assert!(minified.len() <= pretty.len());
+        let expected = colors(sheet);
+        for output in [pretty.clone(), minified.clone()].iter() {
+            let reparsed = CssStylesheet::from_str(&params, *output).unwrap();
+            // Only the unknown at-rules, which libcss ignores
+            assert!(reparsed.diagnostics().iter().all(|d| d.severity == CssSeverityWarning));
+            assert!(reparsed.to_css(CssSerializePretty) == pretty);
+            assert!(reparsed.to_css(CssSerializeMinified) == minified);
+            assert!(colors(reparsed) == expected);
+        }
+    }
+
+    let sheet = CssStylesheet::from_str(&CssStylesheetParams::new("foo"),
+                                        "@media print{ a>b , c { color : red ! important } }").unwrap();
+    assert!(sheet.to_css(CssSerializeMinified) == ~"@media print{a>b,c{color:red!important}}");
+    assert!(sheet.to_css(CssSerializePretty) ==
+            ~"@media print {\n    a > b, c {\n        color: red !important;\n    }\n}\n");
+
+    let sheet = CssStylesheet::from_str(&CssStylesheetParams::new("foo"),
+                                        "@namespace  svg  'http://www.w3.org/2000/svg' ;\
+                                         @font-feature-values  Foo { @swash { fancy : 1 } }").unwrap();
+    assert!(sheet.to_css(CssSerializeMinified) ==
+            ~"@namespace svg url(\"http://www.w3.org/2000/svg\");@font-feature-values Foo{@swash { fancy : 1 }}");
+    assert!(sheet.to_css(CssSerializePretty) ==
+            ~"@namespace svg url(\"http://www.w3.org/2000/svg\");\n@font-feature-values Foo { @swash { fancy : 1 } }\n");
+}
+
+#[test]
+fn test_selector_matching() {
+    use test::fixture::*;
+    use matching::CssSelectorList;
+
+    // Document order is not index order in a complete binary tree
+    let order = document_order();
+    for selector in ["div p", "div > span.x", "h1 p span", "#nothing, p.x",
+                     "p + h1", "span ~ div", "div ~ h1",
+                     "[title]", "p[lang=fr]", "[lang|=en]", "[title~=item]", "h1[title^=n1]",
+                     "[title$=\"0 item\"]", "span[title*=\"7\"]", "div[lang|=fr]",
+                     "span:first-child", "p:last-child", ":only-child", ":empty", "h1:empty"].iter() {
+        let ctx = build_ctx_with(fmt!("%s { color: #123456; }", *selector));
+        let list = CssSelectorList::parse(*selector).unwrap();
+        let root = TreeNode { index: 0 };
+        let found: ~[uint] = list.query_selector_all(&root, &TreeHandler).iter().map(|n| n.index).collect();
+        let mut expected = ~[];
+        for &index in order.iter() {
+            let styled = color_of(&ctx, index).is_some();
+            assert!(list.matches(&TreeNode { index: index }, &TreeHandler) == styled);
+            if styled && index != 0 {
+                expected.push(index);
+            }
+        }
+        assert!(found == expected);
+        match list.query_selector(&root, &TreeHandler) {
+            Some(first) => assert!(first.index == found[0]),
+            None => assert!(found.is_empty())
+        }
+    }
+
+    // Node 2 is the second child of the root, node 3 the first child of node 1
+    let second = CssSelectorList::parse("div > p + h1, span:first-child").unwrap();
+    assert!(second.matches(&TreeNode { index: 2 }, &TreeHandler));
+    assert!(second.matches(&TreeNode { index: 3 }, &TreeHandler));
+    assert!(!second.matches(&TreeNode { index: 4 }, &TreeHandler));
+    assert!(!CssSelectorList::parse("p::before").unwrap().matches(&TreeNode { index: 1 }, &TreeHandler));
+    assert!(CssSelectorList::parse("p >").is_err());
+    assert!(CssSelectorList::parse(":unknown").is_err());
+}
+
+#[test]
+fn test_owned_computed_styles() {
+    use test::fixture::*;
+    use hint::{CssHint, CssHintLength};
+    use types::CssUnitPx;
+    use select::CssPseudoElementNone;
+    use media::CssMedia;
+    use values::{CssColorColor, CssColorInherit};
+    use conversions::ToLl;
+    use computed::{FontSizeComputer, compose_owned};
+    use fixed::CssFixed;
+
+    struct FixedFontSize;
+
+    impl FontSizeComputer for FixedFontSize {
+        fn compute_font_size(&self, _parent: &Option<CssHint>, _child: &CssHint) -> CssHint {
+            CssHintLength(CssUnitPx(CssFixed::from_int(16)))
+        }
+    }
+
+    // Only the root div has a color, the p below it inherits
+    let ctx = build_ctx_with("div { color: #111111; }");
+    let (div, p) = {
+        let div = ctx.select_style(&TreeNode { index: 0 }, CssMedia::all(), None, &TreeHandler);
+        let p = ctx.select_style(&TreeNode { index: 1 }, CssMedia::all(), None, &TreeHandler);
+        (div.owned_computed_style(CssPseudoElementNone), p.owned_computed_style(CssPseudoElementNone))
+    };
+    let shared = div.clone();
+    assert!(match p.style().color() { CssColorInherit => true, _ => false });
+
+    let composed = compose_owned(&shared.style(), &p.style(), @FixedFontSize as @FontSizeComputer);
+    match (composed.style().color(), div.style().color()) {
+        (CssColorColor(a), CssColorColor(b)) => assert!(a.to_ll() == b.to_ll()),
+        _ => fail!()
+    }
+    // Composing left the child alone
+    assert!(match p.style().color() { CssColorInherit => true, _ => false });
+}
+
+#[test]
+fn test_cascade_tree() {
+    use test::fixture::*;
+    use cascade::{cascade_tree, cascade_subtree, CssDefaultFontSizeComputer};
+    use computed::FontSizeComputer;
+    use types::CssUnitPx;
+    use media::CssMedia;
+    use values::{CssColorColor, CssColorInherit, CssFontSizeDimension};
+    use conversions::ToLl;
+    use std::vec;
+
+    // Node 2 is an h1 under the root div, node 5 a p under it and node 11 a span under that
+    let ctx = build_ctx_with("div { color: #111111; } h1 { font-size: 20px; } \
+                              h1 p { font-size: 2em; } span { color: #222222; }");
+    let mut visited = ~[];
+    let mut colors = vec::from_elem(NODE_COUNT, 0u32);
+    let mut sizes = vec::from_elem(NODE_COUNT, 0i32);
+    let mut h1_style = None;
+    do cascade_tree(&TreeNode { index: 0 }, &ctx, CssMedia::all(), &TreeHandler) |node, styles| {
+        visited.push(node.index);
+        if node.index == 2 {
+            h1_style = Some(styles.style.clone());
+        }
+        let style = styles.style.style();
+        colors[node.index] = match style.color() {
+            CssColorColor(color) => color.to_ll(),
+            CssColorInherit => fail!(~"color was not inherited")
+        };
+        sizes[node.index] = match style.font_size() {
+            CssFontSizeDimension(CssUnitPx(size)) => size.to_int(),
+            _ => fail!(~"font size was not made absolute")
+        };
+        assert!(styles.before.is_none());
+    }
+    assert!(visited == document_order());
+    assert!(colors[2] == colors[0]);
+    assert!(colors[5] == colors[0]);
+    assert!(colors[11] != colors[0]);
+    assert!(sizes[2] == 20);
+    assert!(sizes[5] == 40);
+    assert!(sizes[11] == 40);
+
+    // Restyling below the h1 gives the same styles, starting from its style
+    let font_size = @CssDefaultFontSizeComputer::new() as @FontSizeComputer;
+    let mut restyled = 0;
+    do cascade_subtree(&TreeNode { index: 5 }, h1_style.get_ref(), &ctx, CssMedia::all(),
+                       &TreeHandler, font_size) |node, styles| {
+        restyled += 1;
+        let style = styles.style.style();
+        assert!(match style.color() { CssColorColor(color) => color.to_ll() == colors[node.index], _ => false });
+        assert!(match style.font_size() {
+            CssFontSizeDimension(CssUnitPx(size)) => size.to_int() == sizes[node.index],
+            _ => false
+        });
+    }
+    assert!(restyled > 1 && restyled < NODE_COUNT);
+}
+
+#[test]
+#[should_fail]
+fn test_cascade_tree_needs_root() {
+    use test::fixture::*;
+    use cascade::cascade_tree;
+    use media::CssMedia;
+
+    let ctx = build_ctx_with("p { color: #222222; }");
+    do cascade_tree(&TreeNode { index: 1 }, &ctx, CssMedia::all(), &TreeHandler) |_, _| { }
+}
+
+#[test]
+fn test_restyle_damage() {
+    use test::fixture::*;
+    use damage::{diff, RestyleNone, RestyleRepaint, RestyleReflow};
+    use select::CssPseudoElementNone;
+    use media::CssMedia;
+    use computed::CssOwnedComputedStyle;
+
+    fn style_of(data: &str) -> CssOwnedComputedStyle {
+        let ctx = build_ctx_with(data);
+        let results = ctx.select_style(&TreeNode { index: 1 }, CssMedia::all(), None, &TreeHandler);
+        results.owned_computed_style(CssPseudoElementNone)
+    }
+
+    // Node 1 is a p
+    let plain = style_of("p { color: #111111; margin-top: 1px; }");
+    let same = style_of("p { margin-top: 1px; color: #111111; }");
+    let recolored = style_of("p { color: #222222; margin-top: 1px; }");
+    let moved = style_of("p { color: #222222; margin-top: 2px; }");
+    assert!(diff(&plain.style(), &same.style()) == RestyleNone);
+    assert!(diff(&plain.style(), &recolored.style()) == RestyleRepaint);
+    assert!(diff(&plain.style(), &moved.style()) == RestyleReflow);
+    assert!(RestyleRepaint.union(RestyleNone) == RestyleRepaint);
+    assert!(RestyleRepaint.union(RestyleReflow) == RestyleReflow);
+}
+
+// A complete binary tree of nodes and a handler for it, for the tests that
+// select styles or match selectors against a document
+mod fixture {
+    use CssResult;
+    use CssProperty;
+    use types::*;
+    use hint::*;
+    use select::*;
+    use media::CssMedia;
+    use values::{CssColorColor, CssColorInherit};
+    use conversions::ToLl;
+    use wapcaplet::{LwcString, from_rust_string};
+
+    pub static NODE_COUNT: uint = 2000;
+    static NAMES: &'static [&'static str] = &["div", "p", "h1", "span"];
+
+    // Nodes are indexes into a complete binary tree, so they can be sent
+    // between tasks without any boxes
+    pub struct TreeNode {
+        index: uint
+    }
+
+    fn name_of(index: uint) -> &'static str {
+        NAMES[index % NAMES.len()]
+    }
+
+    fn parent_of(index: uint) -> Option<uint> {
+        if index == 0 { None } else { Some((index - 1) / 2) }
+    }
+
+    pub struct TreeHandler;
+
+    impl CssSelectHandler<TreeNode> for TreeHandler {
+        fn node_name(&self, node: &TreeNode) -> CssQName {
+            CssQName {
+                ns: None,
+                name: from_rust_string(name_of(node.index))
+            }
+        }
+
+        fn node_classes(&self, node: &TreeNode) -> Option<~[LwcString]> {
+            if node.index % 2 == 1 { Some(~[from_rust_string("x")]) } else { None }
+        }
+
+        fn node_id(&self, _node: &TreeNode) -> Option<LwcString> { None }
+
+        fn named_parent_node(&self, node: &TreeNode, qname: &CssQName) -> Option<TreeNode> {
+            match parent_of(node.index) {
+                Some(p) if name_of(p) == qname.name.to_str_slice() => Some(TreeNode { index: p }),
+                _ => None
+            }
+        }
+
+        fn parent_node(&self, node: &TreeNode) -> Option<TreeNode> {
+            match parent_of(node.index) {
+                Some(p) => Some(TreeNode { index: p }),
+                None => None
+            }
+        }
+
+        fn node_has_class(&self, node: &TreeNode, name: LwcString) -> bool {
+            node.index % 2 == 1 && name.to_str_slice() == "x"
+        }
+
+        fn node_has_id(&self, _node: &TreeNode, _name: LwcString) -> bool { false }
+
+        fn named_ancestor_node(&self, node: &TreeNode, qname: &CssQName) -> Option<TreeNode> {
+            let mut current = parent_of(node.index);
+            loop {
+                match current {
+                    Some(p) if name_of(p) == qname.name.to_str_slice() => return Some(TreeNode { index: p }),
+                    Some(p) => current = parent_of(p),
+                    None => return None
+                }
+            }
+        }
+
+        fn node_is_root(&self, node: &TreeNode) -> bool { node.index == 0 }
+
+        fn node_is_link(&self, _node: &TreeNode) -> bool { false }
+
+        fn node_is_visited(&self, _node: &TreeNode) -> bool { false }
+
+        fn ua_default_for_property(&self, _property: CssProperty) -> CssHint {
+            CssHintDefault
+        }
+
+        // Even nodes have a title, and all but every fifth node a lang
+        fn node_attribute(&self, node: &TreeNode, name: &str) -> Option<~str> {
+            match name {
+                "title" if node.index % 2 == 0 => Some(fmt!("n%u item", node.index)),
+                "lang" if node.index % 5 != 0 => Some(if node.index % 3 == 0 { ~"en-GB" } else { ~"fr" }),
+                _ => None
+            }
+        }
+    }
+
+    impl CssTreeHandler<TreeNode> for TreeHandler {
+        fn first_child_node(&self, node: &TreeNode) -> Option<TreeNode> {
+            let child = node.index * 2 + 1;
+            if child < NODE_COUNT { Some(TreeNode { index: child }) } else { None }
+        }
+
+        fn next_sibling_node(&self, node: &TreeNode) -> Option<TreeNode> {
+            let sibling = node.index + 1;
+            if node.index % 2 == 1 && sibling < NODE_COUNT { Some(TreeNode { index: sibling }) } else { None }
+        }
+
+        fn prev_sibling_node(&self, node: &TreeNode) -> Option<TreeNode> {
+            if node.index > 0 && node.index % 2 == 0 { Some(TreeNode { index: node.index - 1 }) } else { None }
+        }
+    }
+
+    // Every node, each before its children and its later siblings
+    pub fn document_order() -> ~[uint] {
+        fn visit(index: uint, order: &mut ~[uint]) {
+            if index < NODE_COUNT {
+                order.push(index);
+                visit(index * 2 + 1, order);
+                visit(index * 2 + 2, order);
+            }
+        }
+        let mut order = ~[];
+        visit(0, &mut order);
+        order
+    }
+
+    pub fn build_ctx_with(data: &str) -> CssSelectCtx {
+        use stylesheet::*;
+
+        let resolve: CssUrlResolutionFn = |a,b| resolve_url(a, b);
+        let params = CssStylesheetParams::new("fixture")
+            .level(CssLevel21)
+            .charset("UTF-8")
+            .title("fixture")
+            .resolver(resolve);
+
+        let mut sheet = css_stylesheet_create(&params).unwrap();
+        sheet.append_data(data.as_bytes());
+        sheet.data_done();
+
+        let mut ctx = css_select_ctx_create();
+        ctx.append_sheet(sheet, CssOriginAuthor, CssMedia::all());
+        ctx
+    }
 
     fn resolve_url(_base: &str, _rel: &LwcString) -> CssResult<LwcString> {
         fail!(~"resolving url");
     }
+
+    pub fn color_of(ctx: &CssSelectCtx, index: uint) -> Option<u32> {
+        let results = ctx.select_style_in_tree(&TreeNode { index: index }, CssMedia::all(), None, &TreeHandler);
+        match results.computed_style(CssPseudoElementNone).color() {
+            CssColorInherit => None,
+            CssColorColor(color) => Some(color.to_ll())
+        }
+    }
+}
+
+// Selects a few thousand nodes from several tasks sharing one context and
+// checks that the results match serial selection, and that what the results
+// hand back can be dropped in any task
+mod parallel {
+    use extra::arc::Arc;
+    use std::comm::stream;
+    use std::task::spawn;
+    use select::CssSelectCtx;
+    use test::fixture::*;
+
+    static TASK_COUNT: uint = 4;
+
+    fn build_ctx() -> CssSelectCtx {
+        build_ctx_with("div { color: #111111; }\
+                        p { color: #222222; }\
+                        div p { color: #333333; }\
+                        h1 { color: #444444; }\
+                        span.x { color: #555555; }")
+    }
+
+    #[test]
+    fn select_from_many_tasks() {
+        let ctx = Arc::new(build_ctx());
+
+        let serial: ~[Option<u32>] = range(0, NODE_COUNT).map(|i| color_of(ctx.get(), i)).collect();
+
+        let mut ports = ~[];
+        for task_index in range(0, TASK_COUNT) {
+            let (port, chan) = stream();
+            let ctx = ctx.clone();
+            do spawn {
+                let mut colors = ~[];
+                let mut index = task_index;
+                while index < NODE_COUNT {
+                    colors.push((index, color_of(ctx.get(), index)));
+                    index += TASK_COUNT;
+                }
+                chan.send(colors);
+            }
+            ports.push(port);
+        }
+
+        let mut checked = 0;
+        for port in ports.iter() {
+            for &(index, color) in port.recv().iter() {
+                assert!(color == serial[index]);
+                checked += 1;
+            }
+        }
+        assert!(checked == NODE_COUNT);
+    }
+
+    #[test]
+    fn drop_font_families_from_many_tasks() {
+        use media::CssMedia;
+        use select::CssPseudoElementNone;
+        use values::CssFontFamilyValue;
+
+        let ctx = Arc::new(build_ctx_with("p { font-family: \"Gill Sans\", Verdana, serif }\
+                                           h1 { font-family: Verdana }"));
+        let mut ports = ~[];
+        for task_index in range(0, TASK_COUNT) {
+            let (port, chan) = stream();
+            let ctx = ctx.clone();
+            do spawn {
+                // Each family is read under the lock and dropped outside it
+                let mut families = 0;
+                let mut index = task_index;
+                while index < NODE_COUNT {
+                    let results = ctx.get().select_style(&TreeNode { index: index }, CssMedia::all(),
+                                                         None, &TreeHandler);
+                    let style = results.computed_style(CssPseudoElementNone);
+                    match style.font_family() {
+                        CssFontFamilyValue(names) => {
+                            assert!(names[0] == ~"Gill Sans" || names == ~[~"Verdana"]);
+                            families += 1;
+                        }
+                        _ => ()
+                    }
+                    index += TASK_COUNT;
+                }
+                chan.send(families);
+            }
+            ports.push(port);
+        }
+
+        let mut families = 0;
+        for port in ports.iter() {
+            families += port.recv();
+        }
+        // Every p and h1
+        assert!(families == NODE_COUNT / 2);
+    }
 }
@@ -0,0 +1,770 @@
+// Copyright 2013 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+/*!
+
+A tokenizer for the CSS 2.1 core syntax.
+
+libcss keeps its tokens to itself, so anything that needs to know what the
+source of a sheet looked like (positions, the exact text of a rule) tokenizes
+it again here. Every token carries the line and column it started at.
+
+*/
+
+use std::ascii::StrAsciiExt;
+use std::char;
+use std::str;
+use std::u32;
+use std::util;
+
+#[deriving(Eq, Clone)]
+pub enum Token {
+    TokenIdent(~str),
+    TokenAtKeyword(~str),
+    TokenString(~str),
+    // A string broken by a newline
+    TokenBadString(~str),
+    TokenHash(~str),
+    TokenNumber(~str),
+    TokenPercentage(~str),
+    TokenDimension(~str, ~str),
+    TokenUri(~str),
+    TokenBadUri(~str),
+    TokenUnicodeRange(~str),
+    TokenFunction(~str),
+    TokenDelim(char),
+    TokenColon,
+    TokenSemicolon,
+    TokenComma,
+    TokenOpenBrace,
+    TokenCloseBrace,
+    TokenOpenParen,
+    TokenCloseParen,
+    TokenOpenBracket,
+    TokenCloseBracket,
+    TokenWhitespace,
+    TokenComment(~str),
+    TokenCdo,
+    TokenCdc,
+    TokenIncludes,
+    TokenDashMatch,
+    TokenPrefixMatch,
+    TokenSuffixMatch,
+    TokenSubstringMatch
+}
+
+#[deriving(Eq, Clone)]
+pub struct SourcePosition {
+    // Both one-based, columns count characters rather than bytes
+    line: uint,
+    column: uint
+}
+
+impl Token {
+    /// The token as it could be written in a sheet
+    pub fn to_css(&self) -> ~str {
+        match *self {
+            TokenIdent(ref name) => serialize_identifier(*name),
+            TokenAtKeyword(ref name) => fmt!("@%s", serialize_identifier(*name)),
+            TokenString(ref value) => serialize_string(*value),
+            TokenBadString(ref value) => fmt!("\"%s", *value),
+            TokenHash(ref name) => fmt!("#%s", serialize_name(*name)),
+            TokenNumber(ref number) => number.clone(),
+            TokenPercentage(ref number) => fmt!("%s%%", *number),
+            TokenDimension(ref number, ref unit) => fmt!("%s%s", *number, serialize_identifier(*unit)),
+            TokenUri(ref url) => fmt!("url(%s)", serialize_string(*url)),
+            TokenBadUri(ref url) => fmt!("url(%s", *url),
+            TokenUnicodeRange(ref range) => range.clone(),
+            TokenFunction(ref name) => fmt!("%s(", serialize_identifier(*name)),
+            TokenDelim(c) => str::from_char(c),
+            TokenColon => ~":",
+            TokenSemicolon => ~";",
+            TokenComma => ~",",
+            TokenOpenBrace => ~"{",
+            TokenCloseBrace => ~"}",
+            TokenOpenParen => ~"(",
+            TokenCloseParen => ~")",
+            TokenOpenBracket => ~"[",
+            TokenCloseBracket => ~"]",
+            TokenWhitespace => ~" ",
+            TokenComment(ref text) => fmt!("/*%s*/", *text),
+            TokenCdo => ~"<!--",
+            TokenCdc => ~"-->",
+            TokenIncludes => ~"~=",
+            TokenDashMatch => ~"|=",
+            TokenPrefixMatch => ~"^=",
+            TokenSuffixMatch => ~"$=",
+            TokenSubstringMatch => ~"*="
+        }
+    }
+
+    /// Whitespace and comments, which only separate other tokens
+    pub fn is_trivia(&self) -> bool {
+        match *self {
+            TokenWhitespace | TokenComment(_) => true,
+            _ => false
+        }
+    }
+}
+
+pub struct Tokenizer {
+    priv chars: ~[char],
+    priv pos: uint,
+    priv line: uint,
+    priv column: uint
+}
+
+impl Tokenizer {
+    pub fn new(text: &str) -> Tokenizer {
+        Tokenizer {
+            chars: text.iter().collect(),
+            pos: 0,
+            line: 1,
+            column: 1
+        }
+    }
+
+    /// How many characters have been read, which is where the next token starts
+    pub fn offset(&self) -> uint {
+        self.pos
+    }
+
+    /// Where the next token will start
+    pub fn position(&self) -> SourcePosition {
+        SourcePosition {
+            line: self.line,
+            column: self.column
+        }
+    }
+
+    fn peek(&self, offset: uint) -> Option<char> {
+        if self.pos + offset < self.chars.len() {
+            Some(self.chars[self.pos + offset])
+        } else {
+            None
+        }
+    }
+
+    fn peek_is(&self, offset: uint, c: char) -> bool {
+        self.peek(offset) == Some(c)
+    }
+
+    fn advance(&mut self) -> char {
+        let c = self.chars[self.pos];
+        self.pos += 1;
+        // \r\n counts as a single line break
+        if c == '\n' || c == '\x0c' || (c == '\r' && !self.peek_is(0, '\n')) {
+            self.line += 1;
+            self.column = 1;
+        } else if c != '\r' {
+            self.column += 1;
+        }
+        c
+    }
+
+    fn is_valid_escape(&self, offset: uint) -> bool {
+        self.peek_is(offset, '\\') && match self.peek(offset + 1) {
+            Some(c) => !is_newline(c),
+            None => false
+        }
+    }
+
+    fn starts_name(&self, offset: uint) -> bool {
+        match self.peek(offset) {
+            Some(c) if is_name_char(c) => true,
+            _ => self.is_valid_escape(offset)
+        }
+    }
+
+    fn starts_identifier(&self, offset: uint) -> bool {
+        match self.peek(offset) {
+            Some('-') => match self.peek(offset + 1) {
+                Some(c) if is_name_start(c) => true,
+                _ => self.is_valid_escape(offset + 1)
+            },
+            Some(c) if is_name_start(c) => true,
+            _ => self.is_valid_escape(offset)
+        }
+    }
+
+    fn starts_number(&self, offset: uint) -> bool {
+        let is_digit = |o: uint| match self.peek(o) { Some(c) => c.is_digit(), None => false };
+        match self.peek(offset) {
+            Some('+') | Some('-') => {
+                is_digit(offset + 1) || (self.peek_is(offset + 1, '.') && is_digit(offset + 2))
+            }
+            Some('.') => is_digit(offset + 1),
+            Some(c) => c.is_digit(),
+            None => false
+        }
+    }
+
+    // Expects to be positioned after the backslash
+    fn consume_escape(&mut self) -> char {
+        let mut hex = ~"";
+        while hex.len() < 6 {
+            match self.peek(0) {
+                Some(c) if c.is_digit_radix(16) => {
+                    hex.push_char(self.advance());
+                }
+                _ => break
+            }
+        }
+        if hex.is_empty() {
+            return match self.peek(0) {
+                Some(_) => self.advance(),
+                None => '\ufffd'
+            };
+        }
+        // A single whitespace character ends a hex escape
+        match self.peek(0) {
+            Some(c) if is_whitespace(c) => {
+                self.advance();
+                if c == '\r' && self.peek_is(0, '\n') {
+                    self.advance();
+                }
+            }
+            _ => ()
+        }
+        match u32::from_str_radix(hex.as_slice(), 16) {
+            Some(0) | None => '\ufffd',
+            Some(code) => match char::from_u32(code) {
+                Some(c) => c,
+                None => '\ufffd'
+            }
+        }
+    }
+
+    fn consume_name(&mut self) -> ~str {
+        let mut name = ~"";
+        loop {
+            match self.peek(0) {
+                Some(c) if is_name_char(c) => name.push_char(self.advance()),
+                Some('\\') if self.is_valid_escape(0) => {
+                    self.advance();
+                    name.push_char(self.consume_escape());
+                }
+                _ => return name
+            }
+        }
+    }
+
+    fn consume_whitespace(&mut self) {
+        loop {
+            match self.peek(0) {
+                Some(c) if is_whitespace(c) => { self.advance(); }
+                _ => return
+            }
+        }
+    }
+
+    fn consume_string(&mut self, quote: char) -> Token {
+        self.advance();
+        let mut value = ~"";
+        loop {
+            match self.peek(0) {
+                // Unclosed at the end of the sheet, which closes it
+                None => return TokenString(value),
+                Some(c) if c == quote => {
+                    self.advance();
+                    return TokenString(value);
+                }
+                // The newline is left for the next token
+                Some(c) if is_newline(c) => return TokenBadString(value),
+                Some('\\') => {
+                    self.advance();
+                    match self.peek(0) {
+                        None => (),
+                        Some(c) if is_newline(c) => {
+                            self.advance();
+                            if c == '\r' && self.peek_is(0, '\n') {
+                                self.advance();
+                            }
+                        }
+                        Some(_) => value.push_char(self.consume_escape())
+                    }
+                }
+                Some(_) => value.push_char(self.advance())
+            }
+        }
+    }
+
+    fn consume_number(&mut self) -> ~str {
+        let mut number = ~"";
+        if self.peek_is(0, '+') || self.peek_is(0, '-') {
+            number.push_char(self.advance());
+        }
+        let mut seen_dot = false;
+        loop {
+            match self.peek(0) {
+                Some(c) if c.is_digit() => number.push_char(self.advance()),
+                Some('.') if !seen_dot && match self.peek(1) { Some(d) => d.is_digit(), None => false } => {
+                    seen_dot = true;
+                    number.push_char(self.advance());
+                }
+                _ => return number
+            }
+        }
+    }
+
+    fn consume_numeric(&mut self) -> Token {
+        let number = self.consume_number();
+        if self.starts_identifier(0) {
+            let unit = self.consume_name();
+            TokenDimension(number, unit)
+        } else if self.peek_is(0, '%') {
+            self.advance();
+            TokenPercentage(number)
+        } else {
+            TokenNumber(number)
+        }
+    }
+
+    // Skips what is left of a malformed url(), up to and including the ')'
+    fn consume_bad_url(&mut self, mut value: ~str) -> Token {
+        loop {
+            match self.peek(0) {
+                None => return TokenBadUri(value),
+                Some(')') => {
+                    self.advance();
+                    return TokenBadUri(value);
+                }
+                Some('\\') if self.is_valid_escape(0) => {
+                    self.advance();
+                    value.push_char(self.consume_escape());
+                }
+                Some(_) => value.push_char(self.advance())
+            }
+        }
+    }
+
+    // Expects to be positioned after 'url('
+    fn consume_url(&mut self) -> Token {
+        self.consume_whitespace();
+        match self.peek(0) {
+            None => return TokenBadUri(~""),
+            Some(quote) if quote == '"' || quote == '\'' => {
+                match self.consume_string(quote) {
+                    TokenString(value) => {
+                        self.consume_whitespace();
+                        if self.peek_is(0, ')') {
+                            self.advance();
+                            TokenUri(value)
+                        } else {
+                            self.consume_bad_url(value)
+                        }
+                    }
+                    TokenBadString(value) => self.consume_bad_url(value),
+                    _ => fail!(~"consume_string returned something other than a string")
+                }
+            }
+            Some(_) => {
+                let mut value = ~"";
+                loop {
+                    match self.peek(0) {
+                        None => return TokenBadUri(value),
+                        Some(')') => {
+                            self.advance();
+                            return TokenUri(value);
+                        }
+                        Some(c) if is_whitespace(c) => {
+                            self.consume_whitespace();
+                            return if self.peek_is(0, ')') {
+                                self.advance();
+                                TokenUri(value)
+                            } else {
+                                self.consume_bad_url(value)
+                            };
+                        }
+                        Some('"') | Some('\'') | Some('(') => return self.consume_bad_url(value),
+                        Some('\\') if self.is_valid_escape(0) => {
+                            self.advance();
+                            value.push_char(self.consume_escape());
+                        }
+                        Some('\\') => return self.consume_bad_url(value),
+                        Some(_) => value.push_char(self.advance())
+                    }
+                }
+            }
+        }
+    }
+
+    fn consume_unicode_range(&mut self) -> Token {
+        let mut range = ~"";
+        range.push_char(self.advance());
+        range.push_char(self.advance());
+        let mut digits = 0;
+        while digits < 6 {
+            match self.peek(0) {
+                Some(c) if c.is_digit_radix(16) || c == '?' => {
+                    range.push_char(self.advance());
+                    digits += 1;
+                }
+                _ => break
+            }
+        }
+        if self.peek_is(0, '-') && match self.peek(1) { Some(c) => c.is_digit_radix(16), None => false } {
+            range.push_char(self.advance());
+            digits = 0;
+            while digits < 6 {
+                match self.peek(0) {
+                    Some(c) if c.is_digit_radix(16) => {
+                        range.push_char(self.advance());
+                        digits += 1;
+                    }
+                    _ => break
+                }
+            }
+        }
+        TokenUnicodeRange(range)
+    }
+
+    fn consume_comment(&mut self) -> Token {
+        self.advance();
+        self.advance();
+        let mut text = ~"";
+        loop {
+            match self.peek(0) {
+                None => return TokenComment(text),
+                Some('*') if self.peek_is(1, '/') => {
+                    self.advance();
+                    self.advance();
+                    return TokenComment(text);
+                }
+                Some(_) => text.push_char(self.advance())
+            }
+        }
+    }
+
+    fn next_token(&mut self) -> Option<Token> {
+        let c = match self.peek(0) {
+            Some(c) => c,
+            None => return None
+        };
+
+        let token = if is_whitespace(c) {
+            self.consume_whitespace();
+            TokenWhitespace
+        } else if c == '/' && self.peek_is(1, '*') {
+            self.consume_comment()
+        } else if c == '"' || c == '\'' {
+            self.consume_string(c)
+        } else if c == '#' && self.starts_name(1) {
+            self.advance();
+            TokenHash(self.consume_name())
+        } else if c == '@' && self.starts_identifier(1) {
+            self.advance();
+            TokenAtKeyword(self.consume_name())
+        } else if self.starts_number(0) {
+            self.consume_numeric()
+        } else if c == '<' && self.peek_is(1, '!') && self.peek_is(2, '-') && self.peek_is(3, '-') {
+            for _ in range(0, 4) { self.advance(); }
+            TokenCdo
+        } else if c == '-' && self.peek_is(1, '-') && self.peek_is(2, '>') {
+            for _ in range(0, 3) { self.advance(); }
+            TokenCdc
+        } else if (c == 'u' || c == 'U') && self.peek_is(1, '+') &&
+                  match self.peek(2) { Some(d) => d.is_digit_radix(16) || d == '?', None => false } {
+            self.consume_unicode_range()
+        } else if self.starts_identifier(0) {
+            let name = self.consume_name();
+            if self.peek_is(0, '(') {
+                self.advance();
+                if name.eq_ignore_ascii_case("url") {
+                    self.consume_url()
+                } else {
+                    TokenFunction(name)
+                }
+            } else {
+                TokenIdent(name)
+            }
+        } else if self.peek_is(1, '=') && (c == '~' || c == '|' || c == '^' || c == '$' || c == '*') {
+            self.advance();
+            self.advance();
+            match c {
+                '~' => TokenIncludes,
+                '|' => TokenDashMatch,
+                '^' => TokenPrefixMatch,
+                '$' => TokenSuffixMatch,
+                _ => TokenSubstringMatch
+            }
+        } else {
+            self.advance();
+            match c {
+                ':' => TokenColon,
+                ';' => TokenSemicolon,
+                ',' => TokenComma,
+                '{' => TokenOpenBrace,
+                '}' => TokenCloseBrace,
+                '(' => TokenOpenParen,
+                ')' => TokenCloseParen,
+                '[' => TokenOpenBracket,
+                ']' => TokenCloseBracket,
+                _ => TokenDelim(c)
+            }
+        };
+        Some(token)
+    }
+}
+
+impl Iterator<(Token, SourcePosition)> for Tokenizer {
+    fn next(&mut self) -> Option<(Token, SourcePosition)> {
+        let position = self.position();
+        match self.next_token() {
+            Some(token) => Some((token, position)),
+            None => None
+        }
+    }
+}
+
+pub fn tokenize(text: &str) -> ~[(Token, SourcePosition)] {
+    Tokenizer::new(text).collect()
+}
+
+/// Turns the bytes of a sheet into text for the tokenizer. UTF-16 and UTF-32
+/// are decoded when named by `charset`; anything else is read as UTF-8 where
+/// it is valid and as Latin-1 where it is not, which keeps positions right
+/// for ASCII.
+pub fn decode_source(data: &[u8], charset: Option<&str>) -> ~str {
+    let mut decoder = SourceDecoder::new(charset);
+    let mut text = decoder.push(data);
+    text.push_str(decoder.finish());
+    text
+}
+
+/// Decodes a sheet the way `decode_source` does, a piece at a time. The start
+/// of a character that is split between pieces is kept for the next one
+pub struct SourceDecoder {
+    // Uppercased, or empty if not known
+    priv charset: ~str,
+    // For "UTF-16", which byte order the BOM gave, once it has been seen
+    priv big_endian: Option<bool>,
+    // Bytes of a character the next piece finishes
+    priv partial: ~[u8],
+    // Whether any text has been given back, so a BOM can only come first
+    priv started: bool
+}
+
+impl SourceDecoder {
+    pub fn new(charset: Option<&str>) -> SourceDecoder {
+        let charset = match charset {
+            Some(charset) => charset.to_ascii_upper(),
+            None => ~""
+        };
+        let big_endian = match charset.as_slice() {
+            "UTF-16BE" | "UTF-32BE" => Some(true),
+            "UTF-16LE" | "UTF-32LE" => Some(false),
+            _ => None
+        };
+        SourceDecoder {
+            charset: charset,
+            big_endian: big_endian,
+            partial: ~[],
+            started: false
+        }
+    }
+
+    /// The text of every character `data` finishes
+    pub fn push(&mut self, data: &[u8]) -> ~str {
+        let mut bytes = util::replace(&mut self.partial, ~[]);
+        bytes.push_all(data);
+        let (text, used) = self.decode(bytes, false);
+        self.partial = bytes.slice_from(used).to_owned();
+        self.strip_bom(text)
+    }
+
+    /// The text of whatever is left, now that the sheet has ended
+    pub fn finish(&mut self) -> ~str {
+        let bytes = util::replace(&mut self.partial, ~[]);
+        let (text, _) = self.decode(bytes, true);
+        self.strip_bom(text)
+    }
+
+    // Gives the text of the whole characters in `bytes`, or of all of them
+    // at the end of the sheet, and how many bytes that used
+    fn decode(&mut self, bytes: &[u8], last: bool) -> (~str, uint) {
+        match self.charset.as_slice() {
+            "UTF-16BE" | "UTF-16LE" | "UTF-16" => {
+                if self.big_endian.is_none() {
+                    if bytes.len() < 2 && !last {
+                        return (~"", 0);
+                    }
+                    self.big_endian = Some(bytes.starts_with(&[0xFEu8, 0xFF]));
+                }
+                decode_utf16(bytes, self.big_endian.unwrap(), last)
+            }
+            "UTF-32BE" | "UTF-32LE" => decode_utf32(bytes, self.big_endian.unwrap()),
+            _ => decode_utf8_or_latin1(bytes, last)
+        }
+    }
+
+    // The BOM is not part of the sheet
+    fn strip_bom(&mut self, text: ~str) -> ~str {
+        if self.started || text.is_empty() {
+            return text;
+        }
+        self.started = true;
+        if text.starts_with("\ufeff") {
+            text.slice_from('\ufeff'.len_utf8_bytes()).to_owned()
+        } else {
+            text
+        }
+    }
+}
+
+fn decode_utf16(bytes: &[u8], big_endian: bool, last: bool) -> (~str, uint) {
+    let units: ~[u16] = bytes.chunk_iter(2).filter(|pair| pair.len() == 2).map(|pair| {
+        if big_endian {
+            (pair[0] as u16 << 8) | pair[1] as u16
+        } else {
+            (pair[1] as u16 << 8) | pair[0] as u16
+        }
+    }).collect();
+    let mut text = ~"";
+    let mut i = 0;
+    while i < units.len() {
+        let unit = units[i];
+        if unit >= 0xD800 && unit < 0xDC00 {
+            // The low half of the pair is in the next piece
+            if i + 1 == units.len() && !last {
+                break;
+            }
+            if i + 1 < units.len() && units[i + 1] >= 0xDC00 && units[i + 1] < 0xE000 {
+                let code = 0x10000 + ((unit as u32 - 0xD800) << 10) + (units[i + 1] as u32 - 0xDC00);
+                text.push_char(char_or_replacement(code));
+                i += 2;
+                continue;
+            }
+        }
+        // An unpaired surrogate is not a character, and becomes U+FFFD
+        text.push_char(char_or_replacement(unit as u32));
+        i += 1;
+    }
+    (text, if last { bytes.len() } else { i * 2 })
+}
+
+fn decode_utf32(bytes: &[u8], big_endian: bool) -> (~str, uint) {
+    let whole = bytes.len() - bytes.len() % 4;
+    let text = bytes.slice_to(whole).chunk_iter(4).map(|quad| {
+        let code = if big_endian {
+            (quad[0] as u32 << 24) | (quad[1] as u32 << 16) | (quad[2] as u32 << 8) | quad[3] as u32
+        } else {
+            (quad[3] as u32 << 24) | (quad[2] as u32 << 16) | (quad[1] as u32 << 8) | quad[0] as u32
+        };
+        char_or_replacement(code)
+    }).collect();
+    (text, whole)
+}
+
+fn decode_utf8_or_latin1(bytes: &[u8], last: bool) -> (~str, uint) {
+    let mut text = ~"";
+    let mut i = 0;
+    while i < bytes.len() {
+        let width = utf8_width(bytes[i]);
+        let rest = bytes.slice_from(i + 1);
+        if width > 1 && i + width > bytes.len() && !last && rest.iter().all(|b| *b & 0xC0 == 0x80) {
+            // The rest of the character is in the next piece
+            break;
+        }
+        if width > 0 && i + width <= bytes.len() && str::is_utf8(bytes.slice(i, i + width)) {
+            text.push_str(str::from_utf8(bytes.slice(i, i + width)));
+            i += width;
+        } else {
+            text.push_char(bytes[i] as char);
+            i += 1;
+        }
+    }
+    (text, i)
+}
+
+// How long the UTF-8 sequence starting with `lead` is, or 0 if none can
+fn utf8_width(lead: u8) -> uint {
+    match lead {
+        0x00 .. 0x7F => 1,
+        0xC2 .. 0xDF => 2,
+        0xE0 .. 0xEF => 3,
+        0xF0 .. 0xF4 => 4,
+        _ => 0
+    }
+}
+
+fn char_or_replacement(code: u32) -> char {
+    match char::from_u32(code) {
+        Some(c) => c,
+        None => '\ufffd'
+    }
+}
+
+/// Writes `name` as an identifier, escaping whatever would not tokenize as one
+pub fn serialize_identifier(name: &str) -> ~str {
+    let mut result = ~"";
+    for (i, c) in name.iter().enumerate() {
+        let escape = if i == 0 {
+            !(is_name_start(c) || (c == '-' && name.len() > 1 && !name.char_at(1).is_digit()))
+        } else {
+            !is_name_char(c)
+        };
+        push_maybe_escaped(&mut result, c, escape);
+    }
+    result
+}
+
+/// Writes `name` for the places, like hashes, that only need name characters
+pub fn serialize_name(name: &str) -> ~str {
+    let mut result = ~"";
+    for c in name.iter() {
+        push_maybe_escaped(&mut result, c, !is_name_char(c));
+    }
+    result
+}
+
+/// Writes `value` as a double quoted string
+pub fn serialize_string(value: &str) -> ~str {
+    let mut result = ~"\"";
+    for c in value.iter() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            '\n' => result.push_str("\\a "),
+            '\r' => result.push_str("\\d "),
+            '\x0c' => result.push_str("\\c "),
+            c => result.push_char(c)
+        }
+    }
+    result.push_char('"');
+    result
+}
+
+fn push_maybe_escaped(result: &mut ~str, c: char, escape: bool) {
+    if !escape {
+        result.push_char(c);
+    } else if c.is_digit() || (c as u32) < 0x20 || c == '\x7f' {
+        // Hex escapes end with a space so a following hex digit is not eaten
+        result.push_str(fmt!("\\%x ", c as uint));
+    } else {
+        result.push_char('\\');
+        result.push_char(c);
+    }
+}
+
+fn is_newline(c: char) -> bool {
+    c == '\n' || c == '\r' || c == '\x0c'
+}
+
+pub fn is_whitespace(c: char) -> bool {
+    c == ' ' || c == '\t' || is_newline(c)
+}
+
+fn is_name_start(c: char) -> bool {
+    (c >= 'a' && c <= 'z') || (c >= 'A' && c <= 'Z') || c == '_' || (c as u32) >= 0x80
+}
+
+fn is_name_char(c: char) -> bool {
+    is_name_start(c) || c.is_digit() || c == '-'
+}
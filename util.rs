@@ -8,20 +8,24 @@
 // except according to those terms.
 
 use ll::stylesheet::css_fixed;
+use fixed::CssFixed;
 use types::CssQName;
 use std::libc::c_void;
+use std::task;
+use std::local_data;
+use std::unstable::atomics::{AtomicFlag, INIT_ATOMIC_FLAG, Acquire, Release};
+use std::unstable::finally::Finally;
 use lwcstr_from_rust_str = wapcaplet::from_rust_string;
 
 #[inline]
 pub fn css_fixed_to_float(f: css_fixed) -> f64 {
-    static BEFORE: i32 = 10;
-    f as f64 * 1.0 / ((1i32 << BEFORE) as f64)
+    CssFixed::from_raw(f).to_f64()
 }
 
+/// Rounds to the nearest value and saturates, see `CssFixed::from_f64`
 #[inline]
 pub fn float_to_css_fixed(f: f64) -> css_fixed {
-    static BEFORE: i32 = 10;
-    (f * ((1 << BEFORE) as f64)) as css_fixed
+    CssFixed::from_f64(f).to_raw()
 }
 
 pub fn rust_str_to_net_qname(s: &str) -> CssQName {
@@ -35,9 +39,48 @@ pub fn net_qname_to_rust_str<'a>(qname: &'a CssQName) -> &'a str {
     qname.name.to_str_slice()
 }
 
+// libwapcaplet's intern table and string refcounts are not thread-safe, and
+// nearly every libcss entry point touches them. Calls into libcss take this
+// lock, as do the LwcStrings made and dropped in handler callbacks, which
+// run inside those calls. Nothing the crate returns holds an LwcString (font
+// names come back as plain strings), so the only ones made outside the lock
+// are those a task interns itself, and it must do so inside
+// `with_libcss_lock`.
+static mut LIBCSS_LOCK: AtomicFlag = INIT_ATOMIC_FLAG;
 
-// FIXME: These methods should be unsafe
+// Whether this task holds the lock, so that callbacks can call back into libcss
+local_data_key!(holding_lock: bool)
+
+/// Runs `f` holding the lock on libcss and libwapcaplet. The lock is taken
+/// again freely by the task that holds it. Waiting tasks spin, yielding to
+/// the scheduler each time round, so `f` should not block
+pub fn with_libcss_lock<T>(f: &fn() -> T) -> T {
+    let held = do local_data::get(holding_lock) |held| {
+        match held {
+            Some(&held) => held,
+            None => false
+        }
+    };
+    if held {
+        return f();
+    }
+    unsafe {
+        while LIBCSS_LOCK.test_and_set(Acquire) {
+            task::deschedule();
+        }
+    }
+    local_data::set(holding_lock, true);
+    do f.finally {
+        local_data::set(holding_lock, false);
+        unsafe { LIBCSS_LOCK.clear(Release); }
+    }
+}
+
+/// For nodes that are pointers in disguise. Selection no longer needs this,
+/// as libcss is handed places in a table of nodes rather than the nodes
+/// themselves. Nothing checks that a pointer came from `to_void_ptr`, or that
+/// what it points to is still alive, so both directions are unsafe
 pub trait VoidPtrLike {
-    fn from_void_ptr(ptr: *c_void) -> Self;
-    fn to_void_ptr(&self) -> *c_void;
+    unsafe fn from_void_ptr(ptr: *c_void) -> Self;
+    unsafe fn to_void_ptr(&self) -> *c_void;
 }